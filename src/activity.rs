@@ -0,0 +1,141 @@
+use std::{
+    io,
+    pin::Pin,
+    sync::{atomic::{AtomicU64, AtomicUsize, Ordering}, Arc},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use pin_project_lite::pin_project;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+
+/// `--header-timeout`, `--idle-timeout` and `--send-timeout`, `None` when disabled
+pub struct Timeouts {
+    pub header: Option<Duration>,
+    pub idle: Option<Duration>,
+    pub send: Option<Duration>,
+}
+
+/// Last time a connection read or wrote something, and how many of its requests are in flight
+pub struct Activity {
+    started: Instant,
+    /// Millis since `started`
+    last_read: AtomicU64,
+    last_write: AtomicU64,
+    last_request: AtomicU64,
+    in_flight: AtomicUsize,
+}
+
+/// Counts a request of the connection as in flight until it's dropped (alongside the response body)
+pub struct InFlight(Arc<Activity>);
+
+pin_project! {
+    /// Stream that records its reads and writes into an `Activity`
+    pub struct ActivityStream<S> {
+        #[pin]
+        stream: S,
+        activity: Arc<Activity>,
+    }
+}
+
+
+impl Activity {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            started: Instant::now(),
+            last_read: AtomicU64::new(0),
+            last_write: AtomicU64::new(0),
+            last_request: AtomicU64::new(0),
+            in_flight: AtomicUsize::new(0),
+        })
+    }
+
+    fn now(&self) -> u64 {
+        self.started.elapsed().as_millis() as u64
+    }
+
+    pub fn request(self: &Arc<Self>) -> InFlight {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        self.last_request.store(self.now(), Ordering::Relaxed);
+        InFlight(self.clone())
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Time since anything was read or written
+    pub fn idle_for(&self) -> Duration {
+        let last = self.last_read.load(Ordering::Relaxed).max(self.last_write.load(Ordering::Relaxed));
+        Duration::from_millis(self.now().saturating_sub(last))
+    }
+
+    /// Time since anything was written (or since the last request arrived, if it's more recent)
+    pub fn send_stalled_for(&self) -> Duration {
+        let last = self.last_write.load(Ordering::Relaxed).max(self.last_request.load(Ordering::Relaxed));
+        Duration::from_millis(self.now().saturating_sub(last))
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl<S> ActivityStream<S> {
+    pub fn new(stream: S, activity: Arc<Activity>) -> Self {
+        Self { stream, activity }
+    }
+}
+
+impl<S: AsyncRead> AsyncRead for ActivityStream<S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.project();
+        let filled = buf.filled().len();
+        let r = this.stream.poll_read(cx, buf);
+
+        if buf.filled().len() > filled {
+            this.activity.last_read.store(this.activity.now(), Ordering::Relaxed);
+        }
+        r
+    }
+}
+
+impl<S: AsyncWrite> AsyncWrite for ActivityStream<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.project();
+        let r = this.stream.poll_write(cx, buf);
+
+        if let Poll::Ready(Ok(n)) = &r {
+            if *n > 0 {
+                this.activity.last_write.store(this.activity.now(), Ordering::Relaxed);
+            }
+        }
+        r
+    }
+
+    fn poll_write_vectored(self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &[io::IoSlice<'_>]) -> Poll<io::Result<usize>> {
+        let this = self.project();
+        let r = this.stream.poll_write_vectored(cx, bufs);
+
+        if let Poll::Ready(Ok(n)) = &r {
+            if *n > 0 {
+                this.activity.last_write.store(this.activity.now(), Ordering::Relaxed);
+            }
+        }
+        r
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.stream.is_write_vectored()
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.project().stream.poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.project().stream.poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,217 @@
+use std::{collections::{HashMap, HashSet}, path::Path, sync::Mutex};
+use anyhow::Context;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use hyper::{header::AUTHORIZATION, HeaderMap};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+
+enum PasswordHash {
+    Plain(String),
+    /// `$2y$...` entries (`htpasswd -B`)
+    Bcrypt(String),
+    /// `{SHA}...` entries (`htpasswd -s`)
+    Sha1(Vec<u8>),
+}
+
+pub struct Auth {
+    users: HashMap<String, PasswordHash>,
+    /// Hashes of the `Authorization` headers already verified, bcrypt is too slow to run on every request
+    verified: Mutex<HashSet<[u8; 32]>>,
+}
+
+
+/// Compares without returning early, so the time taken doesn't leak how much of the password matched
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+impl PasswordHash {
+    fn parse(hash: &str) -> anyhow::Result<Self> {
+        if hash.starts_with("$2a$") || hash.starts_with("$2b$") || hash.starts_with("$2y$") {
+            return Ok(Self::Bcrypt(hash.to_string()));
+        }
+
+        if let Some(sha) = hash.strip_prefix("{SHA}") {
+            return Ok(Self::Sha1(BASE64.decode(sha).context("Invalid {SHA} entry")?));
+        }
+
+        if hash.starts_with("$apr1$") || hash.starts_with("$1$") || hash.starts_with("$5$") || hash.starts_with("$6$") {
+            anyhow::bail!("Unsupported hash, use bcrypt (htpasswd -B) or SHA1 (htpasswd -s) entries");
+        }
+
+        Ok(Self::Plain(hash.to_string()))
+    }
+
+    fn verify(&self, password: &str) -> bool {
+        match self {
+            Self::Plain(plain) => constant_time_eq(plain.as_bytes(), password.as_bytes()),
+            Self::Bcrypt(hash) => bcrypt::verify(password, hash).unwrap_or(false),
+            Self::Sha1(hash) => constant_time_eq(&Sha1::digest(password.as_bytes()), hash),
+        }
+    }
+}
+
+impl Auth {
+    /// Loads the `--auth user:pass` credentials and the `--auth-file` entries
+    pub fn load(credentials: &[String], auth_file: Option<&Path>) -> anyhow::Result<Self> {
+        let mut users = HashMap::new();
+
+        for credential in credentials {
+            let (user, password) = credential
+                .split_once(':')
+                .with_context(|| format!("[--auth] Expected user:password, got `{credential}`"))?;
+
+            users.insert(user.to_string(), PasswordHash::Plain(password.to_string()));
+        }
+
+        if let Some(auth_file) = auth_file {
+            let content = std::fs::read_to_string(auth_file)
+                .with_context(|| format!("[--auth-file] Failed to read {}", auth_file.display()))?;
+
+            for (i, line) in content.lines().enumerate() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+
+                let (user, hash) = line
+                    .split_once(':')
+                    .with_context(|| format!("[--auth-file] Line {}: expected user:hash", i + 1))?;
+
+                let hash = PasswordHash::parse(hash)
+                    .with_context(|| format!("[--auth-file] Line {} ({user})", i + 1))?;
+
+                users.insert(user.to_string(), hash);
+            }
+        }
+
+        Ok(Self { users, verified: Mutex::default() })
+    }
+
+    /// Checks the `Authorization: Basic` header, returns the user name if the credentials are valid
+    pub async fn authenticate(&'static self, headers: &HeaderMap) -> Option<String> {
+        let header = headers.get(AUTHORIZATION)?.to_str().ok()?;
+        let (scheme, encoded) = header.split_once(' ')?;
+        if !scheme.eq_ignore_ascii_case("basic") {
+            return None;
+        }
+
+        let decoded = String::from_utf8(BASE64.decode(encoded.trim()).ok()?).ok()?;
+        let (user, password) = decoded.split_once(':')?;
+        let hash = self.users.get(user)?;
+
+        let key: [u8; 32] = Sha256::digest(header.as_bytes()).into();
+        if self.verified.lock().unwrap().contains(&key) {
+            return Some(user.to_string());
+        }
+
+        // bcrypt takes a while, so it doesn't run on the async workers
+        let password = password.to_string();
+        let user = user.to_string();
+        let valid = match hash {
+            PasswordHash::Bcrypt(_) => {
+                let user = user.clone();
+                tokio::task::spawn_blocking(move || self.users[&user].verify(&password))
+                    .await
+                    .unwrap_or(false)
+            }
+            _ => hash.verify(&password),
+        };
+
+        if !valid {
+            return None;
+        }
+
+        self.verified.lock().unwrap().insert(key);
+        Some(user)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    fn basic(user: &str, password: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let value = format!("Basic {}", BASE64.encode(format!("{user}:{password}")));
+        headers.insert(AUTHORIZATION, HeaderValue::from_str(&value).unwrap());
+        headers
+    }
+
+    /// htpasswd file with a bcrypt, a SHA1 and a plain text entry, all with the password `secret`
+    fn auth_file(name: &str, extra: &str) -> std::path::PathBuf {
+        let bcrypt = bcrypt::hash("secret", 4).unwrap();
+        let sha = BASE64.encode(Sha1::digest(b"secret"));
+        let path = std::env::temp_dir().join(format!("htpasswd-{name}-{}", std::process::id()));
+        std::fs::write(&path, format!("# comment\n\nbob:{bcrypt}\nsam:{{SHA}}{sha}\npat:secret\n{extra}")).unwrap();
+        path
+    }
+
+    #[test]
+    fn verifies_every_hash_kind() {
+        let bcrypt = PasswordHash::parse(&bcrypt::hash("secret", 4).unwrap()).unwrap();
+        let sha = PasswordHash::parse(&format!("{{SHA}}{}", BASE64.encode(Sha1::digest(b"secret")))).unwrap();
+        let plain = PasswordHash::parse("secret").unwrap();
+
+        for hash in [bcrypt, sha, plain] {
+            assert!(hash.verify("secret"));
+            assert!(!hash.verify("Secret"));
+            assert!(!hash.verify(""));
+        }
+    }
+
+    #[test]
+    fn rejects_unsupported_hashes() {
+        assert!(PasswordHash::parse("$apr1$salt$hash").is_err());
+        assert!(PasswordHash::parse("$6$salt$hash").is_err());
+        assert!(PasswordHash::parse("{SHA}not base64!").is_err());
+    }
+
+    #[test]
+    fn loads_credentials_and_files() {
+        let path = auth_file("load", "");
+        let auth = Auth::load(&["ann:pass:word".to_string()], Some(&path)).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(auth.users.len(), 4);
+        assert!(auth.users["ann"].verify("pass:word"));
+        assert!(matches!(auth.users["bob"], PasswordHash::Bcrypt(_)));
+        assert!(matches!(auth.users["sam"], PasswordHash::Sha1(_)));
+        assert!(matches!(auth.users["pat"], PasswordHash::Plain(_)));
+
+        assert!(Auth::load(&["no-password".to_string()], None).is_err());
+        assert!(Auth::load(&[], Some(Path::new("/nonexistent/htpasswd"))).is_err());
+    }
+
+    #[test]
+    fn rejects_files_with_apr1_entries() {
+        let path = auth_file("apr1", "eve:$apr1$salt$hash\n");
+        let err = Auth::load(&[], Some(&path)).err().unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert!(format!("{err:#}").contains("Line 6 (eve)"));
+    }
+
+    #[tokio::test]
+    async fn authenticates_basic_credentials() {
+        let path = auth_file("basic", "");
+        let auth: &'static Auth = Box::leak(Box::new(Auth::load(&[], Some(&path)).unwrap()));
+        let _ = std::fs::remove_file(&path);
+
+        for user in ["bob", "sam", "pat"] {
+            assert_eq!(auth.authenticate(&basic(user, "secret")).await.as_deref(), Some(user));
+            // Served from the cache of verified headers the second time
+            assert_eq!(auth.authenticate(&basic(user, "secret")).await.as_deref(), Some(user));
+            assert_eq!(auth.authenticate(&basic(user, "wrong")).await, None);
+        }
+
+        assert_eq!(auth.authenticate(&basic("nobody", "secret")).await, None);
+        assert_eq!(auth.authenticate(&HeaderMap::new()).await, None);
+
+        let mut bearer = HeaderMap::new();
+        bearer.insert(AUTHORIZATION, HeaderValue::from_static("Bearer cGF0OnNlY3JldA=="));
+        assert_eq!(auth.authenticate(&bearer).await, None);
+    }
+}
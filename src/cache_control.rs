@@ -0,0 +1,46 @@
+use globset::{Glob, GlobMatcher};
+use hyper::header::HeaderValue;
+
+
+/// `--cache-control` rule, `glob=value`
+pub struct CacheRule {
+    pattern: GlobMatcher,
+    /// Patterns without `/` match the name of the served file, the others the whole URL path
+    name_only: bool,
+    value: HeaderValue,
+}
+
+
+impl CacheRule {
+    /// `index.html=no-cache`, `/assets/**=public, max-age=31536000, immutable`
+    pub fn parse(rule: &str) -> Result<Self, String> {
+        let (pattern, value) = rule
+            .split_once('=')
+            .ok_or_else(|| format!("Expected `glob=value`, got `{rule}`"))?;
+
+        let pattern = pattern.trim();
+        Ok(Self {
+            pattern: Glob::new(pattern)
+                .map_err(|e| format!("Invalid glob `{pattern}`: {e}"))?
+                .compile_matcher(),
+            name_only: !pattern.contains('/'),
+            value: HeaderValue::from_str(value.trim()).map_err(|_| format!("Invalid Cache-Control value `{value}`"))?,
+        })
+    }
+
+    fn matches(&self, uri_path: &str, file_name: &str) -> bool {
+        match self.name_only {
+            true => self.pattern.is_match(file_name),
+            false => self.pattern.is_match(uri_path),
+        }
+    }
+}
+
+/// `Cache-Control` of the last rule matching the response, `None` if no rule does
+pub fn cache_control(rules: &[CacheRule], uri_path: &str, file_name: &str) -> Option<HeaderValue> {
+    rules
+        .iter()
+        .rev()
+        .find(|rule| rule.matches(uri_path, file_name))
+        .map(|rule| rule.value.clone())
+}
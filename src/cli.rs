@@ -1,13 +1,73 @@
-use std::{collections::HashSet, path::{Path, PathBuf}};
-use clap::{Arg, Command, ValueHint};
+use std::{collections::{HashMap, HashSet}, path::PathBuf, time::Duration};
+use clap::{Arg, ArgAction, ArgMatches, Command, ValueHint};
+use hyper::StatusCode;
+use ipnet::IpNet;
+use crate::{cache_control::CacheRule, compress::Encoding, cors::Cors, headers::{self, HeaderRule, DEFAULT_CSP}, ip_filter::parse_net, local_response::ALLOWED_METHODS};
 
 
+pub enum TlsSource {
+    Files { cert: PathBuf, key: PathBuf },
+    SelfSigned,
+}
+
+pub struct VhostArgs {
+    pub host: String,
+    pub root: PathBuf,
+    pub show_html: bool,
+    pub spa_file: Option<PathBuf>,
+}
+
+/// `share` subcommand, prints a signed link and exits
+pub struct ShareArgs {
+    pub path: String,
+    pub expires: Duration,
+    pub max_downloads: u32,
+    pub base_url: Option<String>,
+    pub port: u16,
+}
+
 pub struct CliArgs {
+    pub root: PathBuf,
+    pub mounts: Vec<(String, PathBuf)>,
+    pub vhosts: Vec<VhostArgs>,
     pub show_html: bool,
     pub only_localhost: bool,
     pub spa_file: Option<PathBuf>,
     pub listen_ports: HashSet<u16>,
     pub log_file: Option<PathBuf>,
+    pub etag_hash: bool,
+    pub tls: Option<TlsSource>,
+    pub tls_ports: HashSet<u16>,
+    pub auth: Vec<String>,
+    pub auth_file: Option<PathBuf>,
+    pub share_secret: Option<String>,
+    pub share_only: bool,
+    pub share: Option<ShareArgs>,
+    pub allow: Vec<IpNet>,
+    pub deny: Vec<IpNet>,
+    pub rate_limit: Option<f64>,
+    pub rate_burst: Option<u32>,
+    pub max_conns_per_ip: Option<u32>,
+    pub limit_rate: Option<u64>,
+    pub global_limit_rate: Option<u64>,
+    pub max_connections: Option<usize>,
+    pub queue_size: usize,
+    pub queue_timeout: Duration,
+    pub header_timeout: Option<Duration>,
+    pub idle_timeout: Option<Duration>,
+    pub send_timeout: Option<Duration>,
+    pub shutdown_timeout: Duration,
+    pub compression: Vec<Encoding>,
+    pub compression_min_size: u64,
+    pub show_sidecars: bool,
+    pub cors: Option<Cors>,
+    pub headers: Vec<HeaderRule>,
+    pub spa_exclude: Vec<String>,
+    /// URL path of the page, by status
+    pub error_pages: HashMap<StatusCode, String>,
+    pub live_reload: bool,
+    pub cache_rules: Vec<CacheRule>,
+    pub no_cache: bool,
 }
 
 impl CliArgs {
@@ -23,6 +83,32 @@ impl CliArgs {
                     .num_args(0..=u16::MAX as usize)
                     .help("Assigns single or multple ports to the http server")
             )
+            .arg(
+                Arg::new("root")
+                    .long("root")
+                    .short('d')
+                    .visible_alias("directory")
+                    .help("Directory that will be served")
+                    .value_hint(ValueHint::DirPath)
+                    .default_value(".")
+                    .num_args(1)
+            )
+            .arg(
+                Arg::new("mount")
+                    .long("mount")
+                    .help("Serves a directory under a URL prefix (/prefix=/path/on/disk), can be repeated")
+                    .value_name("PREFIX=DIR")
+                    .action(ArgAction::Append)
+                    .num_args(1)
+            )
+            .arg(
+                Arg::new("vhost")
+                    .long("vhost")
+                    .help("Serves a directory when the Host header matches (host=/path/on/disk[,html][,spa[=file]]), can be repeated. Other hosts get the default site")
+                    .value_name("HOST=DIR[,OPTIONS]")
+                    .action(ArgAction::Append)
+                    .num_args(1)
+            )
             .arg(
                 Arg::new("html")
                     .long("html")
@@ -38,11 +124,33 @@ impl CliArgs {
             .arg(
                 Arg::new("spa")
                     .long("spa")
-                    .help("Serves the HTML file [default: index.html] located in the current directory when the requested URI points to a direcotry (will set the --html flag to true)")
+                    .help("Serves the HTML file [default: index.html] located in the served directory when the requested URI points to a directory or to a missing path without extension (history API fallback, will set the --html flag to true)")
                     .value_hint(ValueHint::FilePath)
                     .default_missing_value("index.html")
                     .num_args(0..=1)
             )
+            .arg(
+                Arg::new("spa-exclude")
+                    .long("spa-exclude")
+                    .help("Missing paths under this prefix (/api) get 404 instead of the SPA file, can be repeated")
+                    .value_name("PREFIX")
+                    .action(ArgAction::Append)
+                    .num_args(1)
+            )
+            .arg(
+                Arg::new("error-page")
+                    .long("error-page")
+                    .help("Serves a file for the responses with this status (404=404.html), can be repeated. The path is resolved in the site answering the request (root, mounts or --vhost), sites without the file get the built-in page")
+                    .value_name("STATUS=FILE")
+                    .action(ArgAction::Append)
+                    .num_args(1)
+            )
+            .arg(
+                Arg::new("live-reload")
+                    .long("live-reload")
+                    .help("Reloads the HTML pages (--html / --spa) when a file of the served directories changes, stylesheets are swapped without reloading")
+                    .num_args(0)
+            )
             .arg(
                 Arg::new("log-file")
                     .long("log-file")
@@ -51,11 +159,350 @@ impl CliArgs {
                     .num_args(1)
                     .default_missing_value("requests.log")
             )
+            .arg(
+                Arg::new("etag-hash")
+                    .long("etag-hash")
+                    .help("Uses a hash of the file contents as the ETag instead of its modification time and size")
+                    .num_args(0)
+            )
+            .arg(
+                Arg::new("tls-cert")
+                    .long("tls-cert")
+                    .help("PEM certificate (chain) used to serve HTTPS")
+                    .value_hint(ValueHint::FilePath)
+                    .num_args(1)
+                    .requires("tls-key")
+                    .conflicts_with("tls-self-signed")
+            )
+            .arg(
+                Arg::new("tls-key")
+                    .long("tls-key")
+                    .help("PEM private key of the --tls-cert certificate")
+                    .value_hint(ValueHint::FilePath)
+                    .num_args(1)
+                    .requires("tls-cert")
+            )
+            .arg(
+                Arg::new("tls-self-signed")
+                    .long("tls-self-signed")
+                    .help("Serves HTTPS with a certificate for localhost and the LAN IP generated at startup")
+                    .num_args(0)
+            )
+            .arg(
+                Arg::new("tls-ports")
+                    .long("tls-ports")
+                    .help("Ports that will serve HTTPS, the other ports keep serving HTTP [default: all the ports]")
+                    .num_args(1..)
+            )
+            .arg(
+                Arg::new("auth")
+                    .long("auth")
+                    .help("Requires HTTP Basic authentication with the given credentials, can be repeated")
+                    .value_name("USER:PASSWORD")
+                    .action(ArgAction::Append)
+                    .num_args(1)
+            )
+            .arg(
+                Arg::new("auth-file")
+                    .long("auth-file")
+                    .help("Requires HTTP Basic authentication with the users of an htpasswd file (bcrypt, SHA1 or plain text entries)")
+                    .value_hint(ValueHint::FilePath)
+                    .num_args(1)
+            )
+            .arg(
+                Arg::new("share-secret")
+                    .long("share-secret")
+                    .help("Secret used to sign and verify share links (must be the same for the server and the share subcommand)")
+                    .global(true)
+                    .num_args(1)
+            )
+            .arg(
+                Arg::new("share-only")
+                    .long("share-only")
+                    .help("Only serves requests with a valid share link (or valid --auth credentials)")
+                    .requires("share-secret")
+                    .num_args(0)
+            )
+            .arg(
+                Arg::new("allow")
+                    .long("allow")
+                    .help("Only accepts connections from this IP or CIDR range (192.168.1.0/24, fd00::/8), can be repeated")
+                    .value_name("IP/CIDR")
+                    .action(ArgAction::Append)
+                    .num_args(1)
+            )
+            .arg(
+                Arg::new("deny")
+                    .long("deny")
+                    .help("Rejects connections from this IP or CIDR range, takes precedence over --allow, can be repeated")
+                    .value_name("IP/CIDR")
+                    .action(ArgAction::Append)
+                    .num_args(1)
+            )
+            .arg(
+                Arg::new("rate-limit")
+                    .long("rate-limit")
+                    .help("Requests per second allowed for each client IP, the rest get 429 Too Many Requests")
+                    .value_name("REQ/S")
+                    .value_parser(clap::value_parser!(f64))
+                    .num_args(1)
+            )
+            .arg(
+                Arg::new("rate-burst")
+                    .long("rate-burst")
+                    .help("Requests a client IP can make at once before --rate-limit kicks in [default: the --rate-limit value]")
+                    .value_parser(clap::value_parser!(u32).range(1..))
+                    .requires("rate-limit")
+                    .num_args(1)
+            )
+            .arg(
+                Arg::new("max-conns-per-ip")
+                    .long("max-conns-per-ip")
+                    .help("Concurrent connections allowed for each client IP, requests on extra connections get 429 Too Many Requests")
+                    .value_parser(clap::value_parser!(u32).range(1..))
+                    .num_args(1)
+            )
+            .arg(
+                Arg::new("limit-rate")
+                    .long("limit-rate")
+                    .help("Bandwidth allowed for each connection (500KB/s, 5MB/s, 1GB/s)")
+                    .value_name("RATE")
+                    .num_args(1)
+            )
+            .arg(
+                Arg::new("global-limit-rate")
+                    .long("global-limit-rate")
+                    .help("Bandwidth shared by all the transfers (500KB/s, 5MB/s, 1GB/s)")
+                    .value_name("RATE")
+                    .num_args(1)
+            )
+            .arg(
+                Arg::new("max-connections")
+                    .long("max-connections")
+                    .help("Connections served at once, the rest wait in the queue or get 503 Service Unavailable")
+                    .value_parser(clap::value_parser!(u32).range(1..))
+                    .num_args(1)
+            )
+            .arg(
+                Arg::new("queue-size")
+                    .long("queue-size")
+                    .help("Connections that can wait for a free slot when --max-connections is reached [default: 0, refused right away]")
+                    .value_parser(clap::value_parser!(u32))
+                    .requires("max-connections")
+                    .num_args(1)
+            )
+            .arg(
+                Arg::new("queue-timeout")
+                    .long("queue-timeout")
+                    .help("Time a queued connection waits before getting 503 Service Unavailable")
+                    .default_value("10s")
+                    .num_args(1)
+            )
+            .arg(
+                Arg::new("header-timeout")
+                    .long("header-timeout")
+                    .help("Time a client has to complete the TLS handshake and to send the request headers (HTTP/1), 0 disables it")
+                    .default_value("30s")
+                    .num_args(1)
+            )
+            .arg(
+                Arg::new("idle-timeout")
+                    .long("idle-timeout")
+                    .help("Closes connections without requests in flight that sent or received nothing for this long, 0 disables it")
+                    .default_value("60s")
+                    .num_args(1)
+            )
+            .arg(
+                Arg::new("send-timeout")
+                    .long("send-timeout")
+                    .help("Closes connections whose client stops reading a response for this long, 0 disables it")
+                    .default_value("60s")
+                    .num_args(1)
+            )
+            .arg(
+                Arg::new("shutdown-timeout")
+                    .long("shutdown-timeout")
+                    .help("Time the active transfers have to finish after Ctrl-C / SIGTERM")
+                    .default_value("30s")
+                    .num_args(1)
+            )
+            .arg(
+                Arg::new("compression")
+                    .long("compression")
+                    .help("Algorithms used to compress text responses of --html / --spa sites on the fly, in order of preference (zstd, br, gzip), `off` disables it")
+                    .value_name("ALGORITHMS")
+                    .default_value("zstd,br,gzip")
+                    .num_args(1)
+            )
+            .arg(
+                Arg::new("compression-min-size")
+                    .long("compression-min-size")
+                    .help("Responses smaller than this are sent uncompressed")
+                    .value_name("SIZE")
+                    .default_value("1KB")
+                    .num_args(1)
+            )
+            .arg(
+                Arg::new("show-sidecars")
+                    .long("show-sidecars")
+                    .help("Lists the precompressed sidecars (app.js.br, app.js.gz, app.js.zst) next to their original files")
+                    .num_args(0)
+            )
+            .arg(
+                Arg::new("cors")
+                    .long("cors")
+                    .help("Allows cross-origin requests from any origin (Access-Control-Allow-Origin: *), preflight requests are answered automatically")
+                    .num_args(0)
+            )
+            .arg(
+                Arg::new("cors-origin")
+                    .long("cors-origin")
+                    .help("Only allows cross-origin requests from this origin (http://localhost:3000), can be repeated. Enables --cors")
+                    .value_name("ORIGIN")
+                    .action(ArgAction::Append)
+                    .num_args(1)
+            )
+            .arg(
+                Arg::new("cors-methods")
+                    .long("cors-methods")
+                    .help("Methods allowed in cross-origin requests")
+                    .value_name("METHODS")
+                    .default_value(ALLOWED_METHODS)
+                    .num_args(1)
+            )
+            .arg(
+                Arg::new("cors-headers")
+                    .long("cors-headers")
+                    .help("Request headers allowed in cross-origin requests [default: the ones the preflight asks for]")
+                    .value_name("HEADERS")
+                    .num_args(1)
+            )
+            .arg(
+                Arg::new("cors-credentials")
+                    .long("cors-credentials")
+                    .help("Allows cross-origin requests with cookies / Authorization (Access-Control-Allow-Credentials: true), only from the --cors-origin origins")
+                    .requires("cors-origin")
+                    .num_args(0)
+            )
+            .arg(
+                Arg::new("cors-max-age")
+                    .long("cors-max-age")
+                    .help("Time the browsers can cache the preflight responses (10m, 1h, 1d)")
+                    .value_name("DURATION")
+                    .num_args(1)
+            )
+            .arg(
+                Arg::new("header")
+                    .long("header")
+                    .help("Adds a header to the responses, `Name: value` or `/path/glob=Name: value` (/assets/**=Cache-Control: immutable), can be repeated. Later rules win. Strict-Transport-Security is only sent over HTTPS")
+                    .value_name("HEADER")
+                    .action(ArgAction::Append)
+                    .num_args(1)
+            )
+            .arg(
+                Arg::new("secure-headers")
+                    .long("secure-headers")
+                    .help("Adds X-Content-Type-Options, Referrer-Policy, X-Frame-Options, Content-Security-Policy and COOP / COEP (SharedArrayBuffer). Strict-Transport-Security has to be added with --header")
+                    .num_args(0)
+            )
+            .arg(
+                Arg::new("csp")
+                    .long("csp")
+                    .help("Content-Security-Policy of --secure-headers, `off` doesn't send it")
+                    .value_name("POLICY")
+                    .default_value(DEFAULT_CSP)
+                    .num_args(1)
+            )
+            .arg(
+                Arg::new("cache-control")
+                    .long("cache-control")
+                    .help("Cache-Control of the files and listings matching the glob, `glob=value` (index.html=no-cache), can be repeated. Globs without `/` match the file name, the others the URL path. Later rules win")
+                    .value_name("RULE")
+                    .action(ArgAction::Append)
+                    .num_args(1)
+            )
+            .arg(
+                Arg::new("no-cache")
+                    .long("no-cache")
+                    .help("Sends `Cache-Control: no-store` in every response, overriding --cache-control and --header")
+                    .num_args(0)
+            )
+            .subcommand(
+                Command::new("share")
+                    .about("Prints a signed link to a file or directory and exits")
+                    .arg(
+                        Arg::new("path")
+                            .required(true)
+                            .help("URL path of the shared file or directory (everything inside a directory is shared)")
+                    )
+                    .arg(
+                        Arg::new("expires")
+                            .long("expires")
+                            .help("Time until the link expires (30s, 15m, 12h, 7d)")
+                            .default_value("24h")
+                            .num_args(1)
+                    )
+                    .arg(
+                        Arg::new("max-downloads")
+                            .long("max-downloads")
+                            .help("Number of file / zip downloads allowed with the link [default: unlimited]")
+                            .value_parser(clap::value_parser!(u32).range(1..))
+                            .num_args(1)
+                    )
+                    .arg(
+                        Arg::new("base-url")
+                            .long("base-url")
+                            .help("Scheme, host and port prepended to the link [default: http://<LAN IP>:<--port>]")
+                            .num_args(1)
+                    )
+                    .arg(
+                        Arg::new("port")
+                            .long("port")
+                            .help("Port the server listens on, used in the link when there's no --base-url")
+                            .value_parser(clap::value_parser!(u16).range(1..))
+                            .default_value("80")
+                            .conflicts_with("base-url")
+                            .num_args(1)
+                    )
+            )
             .get_matches();
 
+        let root = matches
+            .get_one::<String>("root")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("."));
+
+        let mounts = matches
+            .get_many::<String>("mount")
+            .unwrap_or_default()
+            .filter_map(|m| {
+                let parsed = m
+                    .split_once('=')
+                    .map(|(prefix, dir)| (format!("/{}", prefix.trim_matches('/')), PathBuf::from(dir)))
+                    .filter(|(prefix, _)| prefix != "/");
+
+                if parsed.is_none() {
+                    print_error!("-> [mount] Ignoring `{m}`, expected /prefix=/path/on/disk (use --root for /)");
+                }
+                parsed
+            })
+            .collect();
+
+        let vhosts: Vec<VhostArgs> = matches
+            .get_many::<String>("vhost")
+            .unwrap_or_default()
+            .filter_map(|v| {
+                let parsed = parse_vhost(v);
+                if parsed.is_none() {
+                    print_error!("-> [vhost] Ignoring `{v}`, expected host=/path/on/disk[,html][,spa[=file]]");
+                }
+                parsed
+            })
+            .collect();
+
         let spa_file = matches
             .get_one::<String>("spa")
-            .map(|p| Path::new(".").join(p));
+            .map(|p| root.join(p));
         
         let mut show_html = matches
             .get_one::<bool>("html")
@@ -71,34 +518,407 @@ impl CliArgs {
             .cloned()
             .unwrap_or_default();
     
-        let mut listen_ports = matches
-            .get_many::<String>("ports")
-            .unwrap_or_default()
-            .filter_map(|p| {
-                match p.parse::<u16>() {
-                    Ok(port) => Some(port),
-                    Err(_) => {
-                        print_error!("-> [ports] Ignoring port `{p}`, valid ports: {} - {}", u16::MIN, u16::MAX);
-                        None
-                    }
-                }
-            })
-            .collect::<HashSet<_>>();
+        let mut listen_ports = parse_ports(&matches, "ports");
     
-        if listen_ports.len() == 0 {
+        if listen_ports.is_empty() {
             listen_ports.insert(80);
         }
 
         let log_file = matches
             .get_one::<String>("log-file")
             .map(PathBuf::from);
+
+        let etag_hash = matches
+            .get_one::<bool>("etag-hash")
+            .cloned()
+            .unwrap_or_default();
+
+        let tls = match matches.get_one::<String>("tls-cert") {
+            Some(cert) => Some(TlsSource::Files {
+                cert: PathBuf::from(cert),
+                key: PathBuf::from(matches.get_one::<String>("tls-key").unwrap()),
+            }),
+            None => match matches.get_flag("tls-self-signed") {
+                true => Some(TlsSource::SelfSigned),
+                false => None,
+            }
+        };
+
+        let tls_ports = parse_ports(&matches, "tls-ports");
+
+        let auth = matches
+            .get_many::<String>("auth")
+            .unwrap_or_default()
+            .cloned()
+            .collect();
+
+        let auth_file = matches
+            .get_one::<String>("auth-file")
+            .map(PathBuf::from);
+
+        let share_secret = matches
+            .get_one::<String>("share-secret")
+            .cloned();
+
+        let share_only = matches.get_flag("share-only");
+
+        let allow = parse_nets(&matches, "allow");
+        let deny = parse_nets(&matches, "deny");
+
+        let rate_limit = matches.get_one::<f64>("rate-limit").cloned();
+        if rate_limit.is_some_and(|rate| !rate.is_finite() || rate <= 0.0) {
+            print_error!(1; "-> [rate-limit] Expected a positive number of requests per second");
+        }
+
+        let rate_burst = matches.get_one::<u32>("rate-burst").cloned();
+        let max_conns_per_ip = matches.get_one::<u32>("max-conns-per-ip").cloned();
+
+        let limit_rate = parse_rate_arg(&matches, "limit-rate");
+        let global_limit_rate = parse_rate_arg(&matches, "global-limit-rate");
+
+        let max_connections = matches.get_one::<u32>("max-connections").map(|n| *n as usize);
+        let queue_size = matches.get_one::<u32>("queue-size").map(|n| *n as usize).unwrap_or_default();
+        let queue_timeout = matches.get_one::<String>("queue-timeout").unwrap();
+        let queue_timeout = parse_duration(queue_timeout).unwrap_or_else(|| {
+            print_error!(1; "-> [queue-timeout] Invalid duration `{queue_timeout}`, expected something like 500ms, 10s or 1m");
+            Duration::ZERO
+        });
+
+        let header_timeout = parse_timeout_arg(&matches, "header-timeout");
+        let idle_timeout = parse_timeout_arg(&matches, "idle-timeout");
+        let send_timeout = parse_timeout_arg(&matches, "send-timeout");
+
+        let shutdown_timeout = matches.get_one::<String>("shutdown-timeout").unwrap();
+        let shutdown_timeout = parse_duration(shutdown_timeout).unwrap_or_else(|| {
+            print_error!(1; "-> [shutdown-timeout] Invalid duration `{shutdown_timeout}`, expected something like 10s or 1m");
+            Duration::ZERO
+        });
+
+        let compression = matches.get_one::<String>("compression").unwrap();
+        let compression = match compression.as_str() {
+            "off" | "none" => Vec::new(),
+            algorithms => algorithms
+                .split(',')
+                .map(|name| Encoding::parse(name).unwrap_or_else(|| {
+                    print_error!(1; "-> [compression] Unknown algorithm `{name}`, expected zstd, br or gzip");
+                    Encoding::Gzip
+                }))
+                .collect(),
+        };
+
+        let compression_min_size = matches.get_one::<String>("compression-min-size").unwrap();
+        let compression_min_size = parse_size(compression_min_size).unwrap_or_else(|| {
+            print_error!(1; "-> [compression-min-size] Invalid size `{compression_min_size}`, expected something like 512, 1KB or 1MB");
+            0
+        });
+
+        let show_sidecars = matches.get_flag("show-sidecars");
+
+        let cors_origins = matches
+            .get_many::<String>("cors-origin")
+            .map(|origins| origins.map(|o| o.trim_end_matches('/').to_string()).collect::<Vec<_>>());
+
+        let cors = (matches.get_flag("cors") || cors_origins.is_some()).then(|| Cors {
+            origins: cors_origins,
+            methods: matches.get_one::<String>("cors-methods").cloned().unwrap_or_default(),
+            headers: matches.get_one::<String>("cors-headers").cloned(),
+            credentials: matches.get_flag("cors-credentials"),
+            max_age: matches.get_one::<String>("cors-max-age").map(|max_age| {
+                parse_duration(max_age).unwrap_or_else(|| {
+                    print_error!(1; "-> [cors-max-age] Invalid duration `{max_age}`, expected something like 10m, 1h or 1d");
+                    Duration::ZERO
+                })
+            }),
+        });
+
+        // The preset goes first, so --header can override it
+        let mut headers = Vec::new();
+        if matches.get_flag("secure-headers") {
+            let csp = matches.get_one::<String>("csp").filter(|csp| !csp.eq_ignore_ascii_case("off"));
+            match headers::secure_headers(csp.map(String::as_str)) {
+                Ok(preset) => headers.extend(preset),
+                Err(e) => print_error!(1; "-> [csp] {e}"),
+            }
+        }
+        for header in matches.get_many::<String>("header").unwrap_or_default() {
+            match HeaderRule::parse(header) {
+                Ok(rule) => headers.push(rule),
+                Err(e) => print_error!(1; "-> [header] {e}"),
+            }
+        }
+
+        let spa_exclude = matches
+            .get_many::<String>("spa-exclude")
+            .unwrap_or_default()
+            .map(|prefix| format!("/{}", prefix.trim_matches('/')))
+            .collect();
+
+        let error_pages = matches
+            .get_many::<String>("error-page")
+            .unwrap_or_default()
+            .filter_map(|page| {
+                let parsed = page
+                    .split_once('=')
+                    .and_then(|(status, file)| Some((status.trim().parse::<StatusCode>().ok()?, format!("/{}", file.trim().trim_start_matches('/')))))
+                    .filter(|(status, _)| status.is_client_error() || status.is_server_error());
+
+                // Each site serves its own copy, the file only has to exist in one of them
+                let exists = |file: &str| std::iter::once(&root)
+                    .chain(vhosts.iter().map(|v| &v.root))
+                    .any(|dir| dir.join(&file[1..]).is_file());
+
+                match parsed {
+                    Some((_, ref file)) if !exists(file) => {
+                        print_error!("-> [error-page] Ignoring `{page}`, {file} does not exist in the served dirs");
+                        None
+                    }
+                    None => {
+                        print_error!("-> [error-page] Ignoring `{page}`, expected STATUS=FILE with a 4xx or 5xx status (404=404.html)");
+                        None
+                    }
+                    parsed => parsed,
+                }
+            })
+            .collect();
+
+        let live_reload = matches.get_flag("live-reload");
+
+        let cache_rules = matches
+            .get_many::<String>("cache-control")
+            .unwrap_or_default()
+            .filter_map(|rule| CacheRule::parse(rule).map_err(|e| print_error!(1; "-> [cache-control] {e}")).ok())
+            .collect();
+        let no_cache = matches.get_flag("no-cache");
+
+        let share = matches.subcommand_matches("share").map(|share| {
+            let expires = share.get_one::<String>("expires").unwrap();
+            ShareArgs {
+                path: share.get_one::<String>("path").cloned().unwrap_or_default(),
+                expires: parse_duration(expires).unwrap_or_else(|| {
+                    print_error!(1; "-> [expires] Invalid duration `{expires}`, expected something like 30s, 15m, 12h or 7d");
+                    Duration::ZERO
+                }),
+                max_downloads: share.get_one::<u32>("max-downloads").cloned().unwrap_or_default(),
+                base_url: share.get_one::<String>("base-url").cloned(),
+                port: share.get_one::<u16>("port").cloned().unwrap(),
+            }
+        });
     
         Self {
+            root,
+            mounts,
+            vhosts,
             listen_ports,
             only_localhost,
             spa_file,
             show_html,
-            log_file
+            log_file,
+            etag_hash,
+            tls,
+            tls_ports,
+            auth,
+            auth_file,
+            share_secret,
+            share_only,
+            share,
+            allow,
+            deny,
+            rate_limit,
+            rate_burst,
+            max_conns_per_ip,
+            limit_rate,
+            global_limit_rate,
+            max_connections,
+            queue_size,
+            queue_timeout,
+            header_timeout,
+            idle_timeout,
+            send_timeout,
+            shutdown_timeout,
+            compression,
+            compression_min_size,
+            show_sidecars,
+            cors,
+            headers,
+            spa_exclude,
+            error_pages,
+            live_reload,
+            cache_rules,
+            no_cache,
+        }
+    }
+}
+
+/// `500ms`, `30s`, `15m`, `12h`, `7d` (plain numbers are seconds)
+pub fn parse_duration(duration: &str) -> Option<Duration> {
+    let duration = duration.trim();
+    let split = duration.find(|c: char| !c.is_ascii_digit()).unwrap_or(duration.len());
+    let (value, unit) = duration.split_at(split);
+    let value = value.parse::<u64>().ok()?;
+
+    match unit {
+        "ms" => Some(Duration::from_millis(value)),
+        "" | "s" => Some(Duration::from_secs(value)),
+        "m" => Some(Duration::from_secs(value * 60)),
+        "h" => Some(Duration::from_secs(value * 60 * 60)),
+        "d" => Some(Duration::from_secs(value * 60 * 60 * 24)),
+        _ => None,
+    }
+}
+
+/// Bytes of `512`, `1KB`, `1.5MB` (KiB / MiB / GiB are powers of 1024)
+pub fn parse_size(size: &str) -> Option<u64> {
+    let size = size.trim();
+    let split = size.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(size.len());
+    let (value, unit) = size.split_at(split);
+    let value = value.parse::<f64>().ok()?;
+
+    let multiplier = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1.0,
+        "k" | "kb" => 1e3,
+        "m" | "mb" => 1e6,
+        "g" | "gb" => 1e9,
+        "kib" => 1024.0,
+        "mib" => 1024.0 * 1024.0,
+        "gib" => 1024.0 * 1024.0 * 1024.0,
+        _ => return None,
+    };
+
+    Some((value * multiplier) as u64)
+}
+
+/// Bytes per second of `500KB/s`, `5MB/s`, `1.5GB`
+pub fn parse_rate(rate: &str) -> Option<u64> {
+    let rate = rate.trim();
+    parse_size(rate.strip_suffix("/s").unwrap_or(rate)).filter(|rate| *rate > 0)
+}
+
+fn parse_rate_arg(matches: &ArgMatches, id: &str) -> Option<u64> {
+    let rate = matches.get_one::<String>(id)?;
+    match parse_rate(rate) {
+        Some(rate) => Some(rate),
+        None => {
+            print_error!(1; "-> [{id}] Invalid rate `{rate}`, expected something like 500KB/s or 5MB/s");
+            None
+        }
+    }
+}
+
+/// `None` for `0`
+fn parse_timeout_arg(matches: &ArgMatches, id: &str) -> Option<Duration> {
+    let timeout = matches.get_one::<String>(id)?;
+    match parse_duration(timeout) {
+        Some(timeout) => Some(timeout).filter(|t| !t.is_zero()),
+        None => {
+            print_error!(1; "-> [{id}] Invalid duration `{timeout}`, expected something like 500ms, 30s or 2m (0 disables it)");
+            None
         }
     }
 }
+
+/// `docs.lan=/srv/docs,spa=index.html`
+fn parse_vhost(vhost: &str) -> Option<VhostArgs> {
+    let mut options = vhost.split(',');
+    let (host, root) = options.next()?.split_once('=')?;
+    if host.is_empty() || root.is_empty() {
+        return None;
+    }
+
+    let root = PathBuf::from(root);
+    let mut show_html = false;
+    let mut spa_file = None;
+
+    for option in options {
+        match option.split_once('=') {
+            None if option == "html" => show_html = true,
+            None if option == "spa" => spa_file = Some(root.join("index.html")),
+            Some(("spa", file)) => spa_file = Some(root.join(file)),
+            _ => return None,
+        }
+    }
+
+    Some(VhostArgs {
+        host: host.trim_end_matches('.').to_ascii_lowercase(),
+        show_html: show_html || spa_file.is_some(),
+        root,
+        spa_file,
+    })
+}
+
+fn parse_nets(matches: &ArgMatches, id: &str) -> Vec<IpNet> {
+    matches
+        .get_many::<String>(id)
+        .unwrap_or_default()
+        .map(|net| match parse_net(net) {
+            Some(net) => net,
+            None => {
+                print_error!(1; "-> [{id}] Invalid IP or CIDR range `{net}`");
+                IpNet::default()
+            }
+        })
+        .collect()
+}
+
+fn parse_ports(matches: &ArgMatches, id: &str) -> HashSet<u16> {
+    matches
+        .get_many::<String>(id)
+        .unwrap_or_default()
+        .filter_map(|p| {
+            match p.parse::<u16>() {
+                Ok(port) => Some(port),
+                Err(_) => {
+                    print_error!("-> [{id}] Ignoring port `{p}`, valid ports: {} - {}", u16::MIN, u16::MAX);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("512"), Some(512));
+        assert_eq!(parse_size("512B"), Some(512));
+        assert_eq!(parse_size("1KB"), Some(1000));
+        assert_eq!(parse_size("1k"), Some(1000));
+        assert_eq!(parse_size("1.5MB"), Some(1_500_000));
+        assert_eq!(parse_size("2 GB"), Some(2_000_000_000));
+        assert_eq!(parse_size("1KiB"), Some(1024));
+        assert_eq!(parse_size("1.5mib"), Some(1024 * 1024 * 3 / 2));
+        assert_eq!(parse_size("1GiB"), Some(1 << 30));
+    }
+
+    #[test]
+    fn rejects_invalid_sizes() {
+        assert_eq!(parse_size(""), None);
+        assert_eq!(parse_size("KB"), None);
+        assert_eq!(parse_size("1TB"), None);
+        assert_eq!(parse_size("-1KB"), None);
+        assert_eq!(parse_size("1.2.3MB"), None);
+    }
+
+    #[test]
+    fn parses_rates() {
+        assert_eq!(parse_rate("500KB/s"), Some(500_000));
+        assert_eq!(parse_rate("5MiB/s"), Some(5 * 1024 * 1024));
+        assert_eq!(parse_rate("1.5GB"), Some(1_500_000_000));
+        assert_eq!(parse_rate("100B/s"), Some(100));
+        assert_eq!(parse_rate("0KB/s"), None);
+        assert_eq!(parse_rate("fast"), None);
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_duration("30"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_duration("15m"), Some(Duration::from_secs(15 * 60)));
+        assert_eq!(parse_duration("7d"), Some(Duration::from_secs(7 * 24 * 60 * 60)));
+        assert_eq!(parse_duration("1.5h"), None);
+        assert_eq!(parse_duration("2w"), None);
+    }
+}
@@ -0,0 +1,250 @@
+use std::{collections::HashSet, fs::Metadata, path::{Path, PathBuf}};
+use async_compression::{tokio::bufread::{BrotliEncoder, GzipEncoder, ZstdEncoder}, Level};
+use futures_util::TryStreamExt;
+use http_body_util::{BodyExt, StreamBody};
+use hyper::{body::Frame, header::{HeaderValue, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, VARY}, HeaderMap};
+use mime_guess::{mime, Mime};
+use tokio::{fs, io::{AsyncRead, BufReader}};
+use tokio_util::io::{ReaderStream, StreamReader};
+use crate::{html::DirectoryFile, range::BoxedReader, BoxBodyResponse, CHUNK_SIZE};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Zstd,
+    Brotli,
+    Gzip,
+}
+
+/// Server preference, used to break ties between encodings the client accepts equally
+const ENCODINGS: [Encoding; 3] = [Encoding::Zstd, Encoding::Brotli, Encoding::Gzip];
+
+/// `--compression` algorithms, in order of preference, and `--compression-min-size`
+pub struct Compression {
+    pub encodings: Vec<Encoding>,
+    pub min_size: u64,
+}
+
+
+impl Encoding {
+    /// `Content-Encoding` / `Accept-Encoding` name
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Zstd => "zstd",
+            Self::Brotli => "br",
+            Self::Gzip => "gzip",
+        }
+    }
+
+    /// Extension of the precompressed sidecar files (`app.js.br`)
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Zstd => ".zst",
+            Self::Brotli => ".br",
+            Self::Gzip => ".gz",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "zstd" => Some(Self::Zstd),
+            "br" | "brotli" => Some(Self::Brotli),
+            "gzip" | "x-gzip" => Some(Self::Gzip),
+            _ => None,
+        }
+    }
+}
+
+/// Text formats compress well, images, videos and archives are already compressed
+pub fn is_compressible(mime: &Mime) -> bool {
+    let subtype = mime.subtype().as_str();
+    match mime.type_() {
+        mime::TEXT => true,
+        mime::APPLICATION => matches!(subtype, "json" | "javascript" | "ecmascript" | "xml" | "wasm" | "manifest+json" | "x-sh")
+            || mime.suffix().is_some_and(|s| s == mime::JSON || s == mime::XML),
+        mime::IMAGE => matches!(subtype, "svg" | "bmp" | "x-icon" | "vnd.microsoft.icon"),
+        mime::FONT => matches!(subtype, "ttf" | "otf"),
+        _ => false,
+    }
+}
+
+/// Picks the encoding the client prefers among the `candidates`, `None` if it accepts none of them
+fn preferred(headers: &HeaderMap, candidates: &[Encoding]) -> Option<Encoding> {
+    let accepted = headers
+            .get_all(ACCEPT_ENCODING)
+            .iter()
+            .filter_map(|h| h.to_str().ok())
+            .flat_map(|h| h.split(','))
+            .filter_map(|coding| {
+                let mut params = coding.split(';');
+                let name = params.next()?.trim().to_ascii_lowercase();
+                let q = params
+                    .filter_map(|p| p.trim().strip_prefix("q="))
+                    .find_map(|q| q.trim().parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                Some((name, q))
+            })
+            .collect::<Vec<_>>();
+
+    let quality = |encoding: Encoding| {
+        let named = accepted.iter().find(|(name, _)| Encoding::parse(name) == Some(encoding));
+        named
+            .or_else(|| accepted.iter().find(|(name, _)| name == "*"))
+            .map(|(_, q)| *q)
+            .unwrap_or(0.0)
+    };
+
+    // Highest client quality wins, ties go to the order of the candidates
+    candidates
+        .iter()
+        .map(|e| (*e, quality(*e)))
+        .filter(|(_, q)| *q > 0.0)
+        .fold(None, |best: Option<(Encoding, f32)>, (e, q)| match best {
+            Some((_, best_q)) if best_q >= q => best,
+            _ => Some((e, q)),
+        })
+        .map(|(e, _)| e)
+}
+
+impl Compression {
+    /// Picks the encoding the client prefers among the enabled ones, `None` if the response
+    /// shouldn't be compressed (small, already compressed or not accepted)
+    pub fn negotiate(&self, headers: &HeaderMap, mime: &Mime, size: u64) -> Option<Encoding> {
+        if size < self.min_size || !is_compressible(mime) {
+            return None;
+        }
+
+        preferred(headers, &self.encodings)
+    }
+}
+
+fn sidecar_path(path: &Path, encoding: Encoding) -> PathBuf {
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(encoding.extension());
+    PathBuf::from(sidecar)
+}
+
+/// Precompressed version of `path` (`app.js.br`, `app.js.gz`, `app.js.zst`) the client accepts.
+/// Symlinked sidecars are ignored, they could point outside the served root
+pub async fn find_sidecar(headers: &HeaderMap, path: &Path) -> Option<(Encoding, PathBuf, Metadata)> {
+    if !headers.contains_key(ACCEPT_ENCODING) {
+        return None;
+    }
+
+    let mut sidecars = Vec::new();
+    for encoding in ENCODINGS {
+        let sidecar = sidecar_path(path, encoding);
+        if let Ok(metadata) = fs::symlink_metadata(&sidecar).await {
+            if metadata.is_file() {
+                sidecars.push((encoding, sidecar, metadata));
+            }
+        }
+    }
+
+    let available = sidecars.iter().map(|(encoding, _, _)| *encoding).collect::<Vec<_>>();
+    let encoding = preferred(headers, &available)?;
+    sidecars.into_iter().find(|(e, _, _)| *e == encoding)
+}
+
+/// Removes `name.br` / `name.gz` / `name.zst` from a listing when `name` is in it too
+pub fn hide_sidecars(files: &mut Vec<DirectoryFile>) {
+    let names = files
+        .iter()
+        .filter(|f| !f.is_dir)
+        .map(|f| f.file_name.clone())
+        .collect::<HashSet<_>>();
+
+    files.retain(|f| {
+        f.is_dir || !ENCODINGS.iter().any(|e| {
+            f.file_name
+                .strip_suffix(e.extension())
+                .is_some_and(|original| names.contains(original))
+        })
+    });
+}
+
+/// Compresses the reader on the fly, brotli and gzip use fast levels so the CPU doesn't become the bottleneck
+pub fn encode<R: AsyncRead + Send + Sync + Unpin + 'static>(reader: R, encoding: Encoding) -> BoxedReader {
+    let reader = BufReader::with_capacity(CHUNK_SIZE, reader);
+    match encoding {
+        Encoding::Zstd => Box::new(ZstdEncoder::new(reader)),
+        Encoding::Brotli => Box::new(BrotliEncoder::with_quality(reader, Level::Precise(4))),
+        Encoding::Gzip => Box::new(GzipEncoder::with_quality(reader, Level::Precise(5))),
+    }
+}
+
+/// Compresses the body of an already built response (directory listings), its ETag must already be the encoded one
+pub fn encode_response(response: BoxBodyResponse, encoding: Encoding) -> BoxBodyResponse {
+    let (mut parts, body) = response.into_parts();
+
+    let reader = StreamReader::new(body.into_data_stream());
+    let stream = ReaderStream::with_capacity(encode(reader, encoding), CHUNK_SIZE);
+    let body = StreamBody::new(stream.map_ok(Frame::data)).boxed();
+
+    // The length isn't known until the whole body is compressed
+    parts.headers.remove(CONTENT_LENGTH);
+    parts.headers.insert(CONTENT_ENCODING, HeaderValue::from_static(encoding.as_str()));
+    parts.headers.insert(VARY, HeaderValue::from_static("Accept-Encoding"));
+
+    BoxBodyResponse::from_parts(parts, body)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Encoding; 3] = [Encoding::Zstd, Encoding::Brotli, Encoding::Gzip];
+
+    fn accept(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn ties_go_to_the_server_preference() {
+        assert_eq!(preferred(&accept("gzip, br, zstd"), &ALL), Some(Encoding::Zstd));
+        assert_eq!(preferred(&accept("gzip, br"), &ALL), Some(Encoding::Brotli));
+        assert_eq!(preferred(&accept("gzip, br"), &[Encoding::Gzip, Encoding::Brotli]), Some(Encoding::Gzip));
+    }
+
+    #[test]
+    fn picks_the_highest_quality() {
+        assert_eq!(preferred(&accept("zstd;q=0.5, gzip;q=0.9, br; q=0.8"), &ALL), Some(Encoding::Gzip));
+        assert_eq!(preferred(&accept("GZIP;q=1.0, zstd;q=0.1"), &ALL), Some(Encoding::Gzip));
+        assert_eq!(preferred(&accept("x-gzip"), &ALL), Some(Encoding::Gzip));
+    }
+
+    #[test]
+    fn q_zero_refuses_the_encoding() {
+        assert_eq!(preferred(&accept("zstd;q=0, br;q=0, gzip"), &ALL), Some(Encoding::Gzip));
+        assert_eq!(preferred(&accept("gzip;q=0"), &ALL), None);
+        assert_eq!(preferred(&accept("*;q=0"), &ALL), None);
+    }
+
+    #[test]
+    fn wildcard_covers_the_unnamed_encodings() {
+        assert_eq!(preferred(&accept("*"), &ALL), Some(Encoding::Zstd));
+        assert_eq!(preferred(&accept("zstd;q=0, *"), &ALL), Some(Encoding::Brotli));
+        assert_eq!(preferred(&accept("gzip, *;q=0.1"), &ALL), Some(Encoding::Gzip));
+    }
+
+    #[test]
+    fn needs_an_accepted_encoding() {
+        assert_eq!(preferred(&HeaderMap::new(), &ALL), None);
+        assert_eq!(preferred(&accept("identity"), &ALL), None);
+        assert_eq!(preferred(&accept("deflate, compress"), &ALL), None);
+        assert_eq!(preferred(&accept("br"), &[Encoding::Gzip]), None);
+    }
+
+    #[test]
+    fn negotiates_only_large_text_responses() {
+        let compression = Compression { encodings: ALL.to_vec(), min_size: 1000 };
+        let headers = accept("gzip");
+        assert_eq!(compression.negotiate(&headers, &mime::TEXT_CSS, 1000), Some(Encoding::Gzip));
+        assert_eq!(compression.negotiate(&headers, &mime::TEXT_CSS, 999), None);
+        assert_eq!(compression.negotiate(&headers, &mime::IMAGE_PNG, 5000), None);
+        assert_eq!(compression.negotiate(&headers, &mime::IMAGE_SVG, 5000), Some(Encoding::Gzip));
+    }
+}
@@ -0,0 +1,44 @@
+use std::{sync::{atomic::{AtomicUsize, Ordering}, Arc}, time::Duration};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use crate::logger::{update_stats, StatsMsg};
+
+
+/// Caps the connections served at once, the ones over the cap wait in a bounded queue
+pub struct ConnectionLimit {
+    permits: Arc<Semaphore>,
+    queue_size: usize,
+    queue_timeout: Duration,
+    queued: AtomicUsize,
+}
+
+
+impl ConnectionLimit {
+    pub fn new(max_connections: usize, queue_size: usize, queue_timeout: Duration) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(max_connections)),
+            queue_size,
+            queue_timeout,
+            queued: AtomicUsize::new(0),
+        }
+    }
+
+    /// Waits for a free slot (up to the queue timeout). `None` if the queue is full or the wait timed out,
+    /// the connection must be refused then
+    pub async fn admit(&self) -> Option<OwnedSemaphorePermit> {
+        if let Ok(permit) = self.permits.clone().try_acquire_owned() {
+            return Some(permit);
+        }
+
+        if self.queued.fetch_add(1, Ordering::SeqCst) >= self.queue_size {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+
+        update_stats(StatsMsg::ConnectionQueued);
+        let permit = tokio::time::timeout(self.queue_timeout, self.permits.clone().acquire_owned()).await;
+        self.queued.fetch_sub(1, Ordering::SeqCst);
+        update_stats(StatsMsg::ConnectionDequeued);
+
+        permit.ok().and_then(Result::ok)
+    }
+}
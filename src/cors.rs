@@ -0,0 +1,104 @@
+use std::time::Duration;
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::{
+    body::Incoming,
+    header::{
+        HeaderValue, ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+        ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD,
+        ORIGIN, SERVER, VARY,
+    },
+    Method, Request, Response, StatusCode,
+};
+use crate::{BoxBodyResponse, SERVER_NAME_HEADER};
+
+
+/// `--cors` (any origin) and its `--cors-*` settings
+pub struct Cors {
+    /// `None` allows any origin
+    pub origins: Option<Vec<String>>,
+    pub methods: String,
+    /// `None` allows the headers the preflight asks for
+    pub headers: Option<String>,
+    /// Only set along with `origins`
+    pub credentials: bool,
+    pub max_age: Option<Duration>,
+}
+
+
+/// `OPTIONS` request sent by the browser before the actual cross-origin request
+pub fn is_preflight(req: &Request<Incoming>) -> bool {
+    req.method() == Method::OPTIONS
+        && req.headers().contains_key(ORIGIN)
+        && req.headers().contains_key(ACCESS_CONTROL_REQUEST_METHOD)
+}
+
+impl Cors {
+    /// Value of `Access-Control-Allow-Origin` for the request `Origin`, `None` if it isn't allowed
+    fn allow_origin(&self, origin: &HeaderValue) -> Option<HeaderValue> {
+        match &self.origins {
+            // Credentials require --cors-origin, echoing any origin would let every site read the files
+            None => Some(HeaderValue::from_static("*")),
+            Some(origins) => {
+                let origin_str = origin.to_str().ok()?.trim_end_matches('/');
+                origins
+                    .iter()
+                    .any(|o| o.eq_ignore_ascii_case(origin_str))
+                    .then(|| origin.clone())
+            }
+        }
+    }
+
+    /// `Vary: Origin` unless every origin gets the same `*`
+    fn varies(&self) -> bool {
+        self.origins.is_some()
+    }
+
+    /// Answers a preflight request, without CORS headers if the origin isn't allowed (so the browser blocks the request).
+    /// The origin headers are added by `apply`, like for every other response
+    pub fn preflight(&self, req: &Request<Incoming>) -> BoxBodyResponse {
+        let body = Full::new(Bytes::new())
+            .map_err(|never| match never {})
+            .boxed();
+
+        let mut response = Response::builder()
+            .header(SERVER, SERVER_NAME_HEADER)
+            .status(StatusCode::NO_CONTENT);
+
+        let req_headers = req.headers();
+        if req_headers.get(ORIGIN).and_then(|o| self.allow_origin(o)).is_some() {
+            response = response.header(ACCESS_CONTROL_ALLOW_METHODS, &self.methods);
+
+            let allow_headers = match &self.headers {
+                Some(headers) => HeaderValue::from_str(headers).ok(),
+                None => req_headers.get(ACCESS_CONTROL_REQUEST_HEADERS).cloned(),
+            };
+            if let Some(allow_headers) = allow_headers {
+                response = response.header(ACCESS_CONTROL_ALLOW_HEADERS, allow_headers);
+            }
+
+            if let Some(max_age) = self.max_age {
+                response = response.header(ACCESS_CONTROL_MAX_AGE, max_age.as_secs());
+            }
+        }
+
+        response.body(body).unwrap()
+    }
+
+    /// Adds the CORS headers to the response of a cross-origin request
+    pub fn apply(&self, origin: Option<&HeaderValue>, response: &mut BoxBodyResponse) {
+        let headers = response.headers_mut();
+        if self.varies() {
+            headers.append(VARY, HeaderValue::from_static("Origin"));
+        }
+
+        let Some(allow_origin) = origin.and_then(|o| self.allow_origin(o)) else {
+            return;
+        };
+
+        headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, allow_origin);
+        if self.credentials {
+            headers.insert(ACCESS_CONTROL_ALLOW_CREDENTIALS, HeaderValue::from_static("true"));
+        }
+    }
+}
@@ -1,9 +1,11 @@
 use std::borrow::Cow;
+use std::path::{Path, PathBuf};
 use std::time::SystemTime;
 
 use chrono::{DateTime, Local};
 use futures_util::TryStreamExt;
-use http_body_util::{BodyExt, StreamBody};
+use bytes::Bytes;
+use http_body_util::{combinators::BoxBody, BodyExt, Empty, StreamBody};
 use hyper::body::Frame;
 use hyper::header::{CONTENT_DISPOSITION, CONTENT_TYPE, SERVER};
 use hyper::{Response, StatusCode, Result as HyperResult};
@@ -11,7 +13,9 @@ use tokio::fs::File;
 use tokio_util::io::ReaderStream;
 use walkdir::WalkDir;
 use zipit::{Archive, FileDateTime};
+use crate::jail;
 use crate::reader_inspector::ReaderInspector;
+use crate::throttle::Throttle;
 use crate::{BoxBodyResponse, CHUNK_SIZE, SERVER_NAME_HEADER};
 
 fn parse_path_name(path: Cow<str>) -> String {
@@ -26,23 +30,41 @@ fn parse_path_name(path: Cow<str>) -> String {
     }
 }
 
-pub async fn dir_to_zip(dir: impl AsRef<str>) -> HyperResult<BoxBodyResponse> {
+/// Streams `dir` (already resolved inside `root`) as a zip file, skipping the `excluded` paths.
+/// `name` is the requested path used to name the zip
+pub async fn dir_to_zip(root: &Path, dir: PathBuf, excluded: Vec<PathBuf>, name: &str, throttle: Throttle) -> HyperResult<BoxBodyResponse> {
     let (a, b) = tokio::io::duplex(CHUNK_SIZE);
     
-    let dir = dir.as_ref();
-    let dir_clone = dir.to_string();
+    let root = root.to_path_buf();
+    let dir_clone = dir;
     tokio::spawn(async move {
         let mut archive = Archive::new(a);
 
-        for entry in WalkDir::new(&dir_clone) {
+        let mut walker = WalkDir::new(&dir_clone).into_iter();
+        while let Some(entry) = walker.next() {
             let Ok(entry) = entry else {
                 continue;
             };
+
+            // Other mount points hide these paths
+            if excluded.iter().any(|p| entry.path().starts_with(p)) {
+                if entry.file_type().is_dir() {
+                    walker.skip_current_dir();
+                }
+                continue;
+            }
+
             if entry.file_type().is_dir() {
                 continue;
             }
     
             let path = entry.path();
+
+            // Symlinks pointing outside the served root are skipped
+            if jail::confine(&root, path).await.is_err() {
+                continue;
+            }
+
             let name = parse_path_name(path.strip_prefix(&dir_clone).unwrap().to_string_lossy());
             let mut file = match File::open(path).await {
                 Err(_err) => continue, //Some(error_response(err.to_string())), // panic!("\n{}\n", err),
@@ -61,26 +83,31 @@ pub async fn dir_to_zip(dir: impl AsRef<str>) -> HyperResult<BoxBodyResponse> {
             }
         }
 
-        if let Err(_err) = archive.finalize().await {
-            return //Some(error_response(err.to_string())); // panic!("\n{}\n", err)
-        }
-       //None
+        let _ = archive.finalize().await;
     });
 
-    let reader_stream = ReaderInspector::new(ReaderStream::new(b));
+    let reader_stream = ReaderInspector::new(ReaderStream::new(b), throttle);
     let body = StreamBody::new(reader_stream.map_ok(Frame::data)).boxed();
-    let zip_name = match dir {
-        "." => "result".to_string(),
-        _ => format!("{}", dir.replace("/", "_")),
+
+    Ok(zip_response(name, body))
+}
+
+/// Headers of the zip of a directory, HEAD requests get them without building the archive
+pub fn zip_head(name: &str) -> BoxBodyResponse {
+    zip_response(name, Empty::new().map_err(|never| match never {}).boxed())
+}
+
+fn zip_response(name: &str, body: BoxBody<Bytes, std::io::Error>) -> BoxBodyResponse {
+    let zip_name = match name {
+        "" => "result".to_string(),
+        _ => name.replace("/", "_"),
     };
 
-    let response = Response::builder()
+    Response::builder()
         .status(StatusCode::OK)
         .header(CONTENT_TYPE, "application/zip")
         .header(CONTENT_DISPOSITION, format!("attachment; filename={}.zip", zip_name))
         .header(SERVER, SERVER_NAME_HEADER)
         .body(body)
-        .unwrap();
-
-    Ok(response)
+        .unwrap()
 }
\ No newline at end of file
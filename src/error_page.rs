@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+use bytes::Bytes;
+use http_body_util::{BodyExt, Full};
+use hyper::{header::{HeaderValue, ACCEPT, CONTENT_LENGTH, CONTENT_TYPE}, HeaderMap, StatusCode};
+use mime_guess::mime;
+use tokio::fs;
+use crate::{html::build_error_html, jail, sites::Site, BoxBodyResponse};
+
+
+/// `--error-page` URL paths, by status code. Every site resolves them in its own dirs
+pub struct ErrorPages(pub HashMap<StatusCode, String>);
+
+
+/// `fetch` calls (`Accept: application/json`) get the error as JSON
+pub fn wants_json(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .any(|h| h.contains("application/json"))
+}
+
+fn message(status: StatusCode) -> &'static str {
+    match status {
+        StatusCode::UNAUTHORIZED => "Authentication is required to access this page.",
+        StatusCode::FORBIDDEN => "You don't have permission to access this page.",
+        StatusCode::NOT_FOUND => "The requested page doesn't exist.",
+        StatusCode::METHOD_NOT_ALLOWED => "The request method isn't supported.",
+        StatusCode::GONE => "This link has expired.",
+        StatusCode::TOO_MANY_REQUESTS => "Too many requests, try again later.",
+        StatusCode::INTERNAL_SERVER_ERROR => "Something went wrong while handling the request.",
+        StatusCode::SERVICE_UNAVAILABLE => "The server is busy, try again later.",
+        _ => "",
+    }
+}
+
+/// Replaces the plain text body of an error response (4xx / 5xx) with JSON, the `--error-page` of its status
+/// of the `site` or the built-in HTML page. The other headers (`Allow`, `Retry-After`, `WWW-Authenticate`...) are kept
+pub async fn render(response: BoxBodyResponse, json: bool, pages: Option<&ErrorPages>, site: &Site) -> BoxBodyResponse {
+    let status = response.status();
+    if !status.is_client_error() && !status.is_server_error() {
+        return response;
+    }
+
+    let reason = status.canonical_reason().unwrap_or_default();
+    let page = match pages.and_then(|pages| pages.0.get(&status)) {
+        Some(page) if !json => {
+            let (mount, path) = site.mounts.find(page);
+            jail::resolve(&mount.dir, path).await.ok()
+        }
+        _ => None,
+    };
+
+    let custom_page = match page {
+        Some(page) => match fs::read(&page).await {
+            Ok(content) => Some((mime_guess::from_path(page).first_or(mime::TEXT_HTML), content)),
+            Err(e) => {
+                print_error!("[--error-page] Failed to read {}: {e}", page.display());
+                None
+            }
+        },
+        None => None,
+    };
+
+    let (content_type, body) = match custom_page {
+        Some((mime, content)) => (mime.to_string(), Bytes::from(content)),
+        None if json => (
+            mime::APPLICATION_JSON.to_string(),
+            Bytes::from(serde_json::json!({ "status": status.as_u16(), "error": reason }).to_string()),
+        ),
+        None => (
+            mime::TEXT_HTML.to_string(),
+            Bytes::from(build_error_html(&format!("{} {reason}", status.as_u16()), message(status))),
+        ),
+    };
+
+    let (mut parts, _) = response.into_parts();
+    if let Ok(content_type) = HeaderValue::from_str(&content_type) {
+        parts.headers.insert(CONTENT_TYPE, content_type);
+    }
+    parts.headers.insert(CONTENT_LENGTH, HeaderValue::from(body.len()));
+
+    let body = Full::new(body)
+        .map_err(|never| match never {})
+        .boxed();
+    BoxBodyResponse::from_parts(parts, body)
+}
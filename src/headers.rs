@@ -0,0 +1,84 @@
+use globset::{Glob, GlobMatcher};
+use hyper::{header::{HeaderName, HeaderValue, STRICT_TRANSPORT_SECURITY}, HeaderMap};
+
+
+/// `--header` rule, `Name: value` or `/path/glob=Name: value`
+pub struct HeaderRule {
+    /// `None` matches every path
+    pattern: Option<GlobMatcher>,
+    name: HeaderName,
+    value: HeaderValue,
+    /// Only sent over HTTPS connections (HSTS)
+    tls_only: bool,
+}
+
+/// Headers added to every response whose path matches their rule, later rules win
+pub struct CustomHeaders(pub Vec<HeaderRule>);
+
+
+impl HeaderRule {
+    pub fn new(pattern: Option<&str>, name: &str, value: &str) -> Result<Self, String> {
+        let pattern = match pattern {
+            Some(pattern) => Some(
+                Glob::new(pattern)
+                    .map_err(|e| format!("Invalid path glob `{pattern}`: {e}"))?
+                    .compile_matcher(),
+            ),
+            None => None,
+        };
+
+        let name = HeaderName::from_bytes(name.trim().as_bytes()).map_err(|_| format!("Invalid header name `{name}`"))?;
+        Ok(Self {
+            pattern,
+            tls_only: name == STRICT_TRANSPORT_SECURITY,
+            name,
+            value: HeaderValue::from_str(value.trim()).map_err(|_| format!("Invalid header value `{value}`"))?,
+        })
+    }
+
+    /// Header names can't contain `=`, so the glob is everything before the last `=` of the name part
+    pub fn parse(rule: &str) -> Result<Self, String> {
+        let (name, value) = rule
+            .split_once(':')
+            .ok_or_else(|| format!("Expected `Name: value` or `/path/glob=Name: value`, got `{rule}`"))?;
+
+        match name.rsplit_once('=') {
+            Some((pattern, name)) => Self::new(Some(pattern), name, value),
+            None => Self::new(None, name, value),
+        }
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        self.pattern.as_ref().is_none_or(|p| p.is_match(path))
+    }
+}
+
+impl CustomHeaders {
+    pub fn apply(&self, path: &str, is_tls: bool, headers: &mut HeaderMap) {
+        for rule in self.0.iter().filter(|r| r.matches(path) && (is_tls || !r.tls_only)) {
+            headers.insert(rule.name.clone(), rule.value.clone());
+        }
+    }
+}
+
+/// The directory listing uses inline styles and scripts
+pub const DEFAULT_CSP: &str = "default-src 'self'; style-src 'self' 'unsafe-inline'; script-src 'self' 'unsafe-inline'; img-src 'self' data:; object-src 'none'; frame-ancestors 'self'";
+
+/// `--secure-headers` preset. HSTS is left out, browsers would apply it to every port of the host
+/// (plain HTTP ones and other dev servers on `localhost` included) for as long as its max-age
+pub fn secure_headers(csp: Option<&str>) -> Result<Vec<HeaderRule>, String> {
+    let mut rules = vec![
+        HeaderRule::new(None, "X-Content-Type-Options", "nosniff")?,
+        HeaderRule::new(None, "Referrer-Policy", "strict-origin-when-cross-origin")?,
+        HeaderRule::new(None, "X-Frame-Options", "SAMEORIGIN")?,
+        // Cross-origin isolation, required by SharedArrayBuffer
+        HeaderRule::new(None, "Cross-Origin-Opener-Policy", "same-origin")?,
+        HeaderRule::new(None, "Cross-Origin-Embedder-Policy", "require-corp")?,
+    ];
+
+    if let Some(csp) = csp {
+        rules.push(HeaderRule::new(None, "Content-Security-Policy", csp)?);
+    }
+
+    Ok(rules)
+}
@@ -130,6 +130,25 @@ r#"<head>
 }
 
 
+/// Error page with the look of the directory listing
+pub fn build_error_html(status: &str, message: &str) -> String {
+    format!(
+r#"<head>
+    <title>{status}</title>
+    <style>{HTML_STYLE}</style>
+</head>
+<body>
+    <h1>{status}</h1>
+    <p>{message}</p>
+    <div>
+        <button onclick="window.history.back()">Back</button>
+        <a href="/">Go to the root</a>
+    </div>
+</body>"#
+    )
+}
+
+
 /* 
 use futures_util::TryStreamExt;
 use http_body_util::{BodyExt, StreamBody};
@@ -0,0 +1,31 @@
+use std::net::IpAddr;
+use ipnet::IpNet;
+
+
+/// `--allow` / `--deny` rules, checked right after accepting a connection
+pub struct IpFilter {
+    pub allow: Vec<IpNet>,
+    pub deny: Vec<IpNet>,
+}
+
+impl IpFilter {
+    /// Deny rules win over allow rules, and when there are allow rules
+    /// only the peers matching one of them are accepted
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        // IPv4 peers on dual stack sockets show up as ::ffff:a.b.c.d
+        let ip = ip.to_canonical();
+
+        if self.deny.iter().any(|net| net.contains(&ip)) {
+            return false;
+        }
+
+        self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&ip))
+    }
+}
+
+/// Parses `192.168.1.0/24`, `fd00::/8` or a single address (`10.0.0.7`)
+pub fn parse_net(net: &str) -> Option<IpNet> {
+    net.parse::<IpNet>()
+        .ok()
+        .or_else(|| net.parse::<IpAddr>().ok().map(IpNet::from))
+}
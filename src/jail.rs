@@ -0,0 +1,112 @@
+use std::{io::{self, ErrorKind}, path::{Component, Path, PathBuf}};
+
+
+pub enum JailError {
+    /// The path tries to escape the served root (`..` segments, absolute paths, symlinks pointing outside...)
+    Forbidden,
+    /// The path doesn't exist
+    NotFound,
+    /// The path can't be resolved for any other reason (permission denied...)
+    Io(io::Error),
+}
+
+
+/// Resolves a decoded URI path (`/dir/file.txt`) to a canonical path inside `root`.
+/// `root` must already be canonical
+pub async fn resolve(root: &Path, uri_path: &str) -> Result<PathBuf, JailError> {
+    let mut path = root.to_path_buf();
+
+    // Both separators are checked, `\` is a separator on windows
+    for segment in uri_path.split(['/', '\\']) {
+        if segment.is_empty() || segment == "." {
+            continue;
+        }
+
+        if segment.contains('\0') {
+            return Err(JailError::Forbidden);
+        }
+
+        // Rejects `..`, drive letters and anything that isn't a plain file name
+        let mut components = Path::new(segment).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(_)), None) => path.push(segment),
+            _ => return Err(JailError::Forbidden),
+        }
+    }
+
+    confine(root, &path).await
+}
+
+/// Canonicalizes the path (following symlinks) and checks that it's still inside `root`
+pub async fn confine(root: &Path, path: &Path) -> Result<PathBuf, JailError> {
+    let canonical = tokio::fs::canonicalize(path).await.map_err(|e| match e.kind() {
+        ErrorKind::NotFound | ErrorKind::NotADirectory => JailError::NotFound,
+        _ => JailError::Io(e),
+    })?;
+
+    match canonical.starts_with(root) {
+        true => Ok(canonical),
+        false => Err(JailError::Forbidden),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Temporary root with `public/file.txt`, `secret.txt` next to it and symlinks to both
+    fn test_root(name: &str) -> (PathBuf, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("jail-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let public = dir.join("public");
+        std::fs::create_dir_all(public.join("sub")).unwrap();
+        std::fs::write(public.join("file.txt"), "public").unwrap();
+        std::fs::write(dir.join("secret.txt"), "secret").unwrap();
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(dir.join("secret.txt"), public.join("escape.txt")).unwrap();
+            std::os::unix::fs::symlink(public.join("file.txt"), public.join("sub/inside.txt")).unwrap();
+        }
+        (dir.clone(), std::fs::canonicalize(public).unwrap())
+    }
+
+    #[tokio::test]
+    async fn resolves_paths_inside_the_root() {
+        let (dir, root) = test_root("inside");
+        assert!(matches!(resolve(&root, "/file.txt").await, Ok(p) if p == root.join("file.txt")));
+        assert!(matches!(resolve(&root, "/./file.txt").await, Ok(p) if p == root.join("file.txt")));
+        assert!(matches!(resolve(&root, "/missing.txt").await, Err(JailError::NotFound)));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn rejects_parent_segments() {
+        let (dir, root) = test_root("parent");
+        assert!(matches!(resolve(&root, "/../secret.txt").await, Err(JailError::Forbidden)));
+        assert!(matches!(resolve(&root, "/sub/../../secret.txt").await, Err(JailError::Forbidden)));
+        // `%2e%2e` decoded by the caller
+        assert!(matches!(resolve(&root, &urlencoding::decode("/%2e%2e/secret.txt").unwrap()).await, Err(JailError::Forbidden)));
+        // Not decoded, it's a plain file name
+        assert!(matches!(resolve(&root, "/%2e%2e/secret.txt").await, Err(JailError::NotFound)));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn rejects_backslashes_and_nul_bytes() {
+        let (dir, root) = test_root("separators");
+        assert!(matches!(resolve(&root, "/..\\secret.txt").await, Err(JailError::Forbidden)));
+        assert!(matches!(resolve(&root, "\\..\\secret.txt").await, Err(JailError::Forbidden)));
+        assert!(matches!(resolve(&root, "/file.txt\0.png").await, Err(JailError::Forbidden)));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn follows_symlinks_only_inside_the_root() {
+        let (dir, root) = test_root("symlinks");
+        assert!(matches!(resolve(&root, "/escape.txt").await, Err(JailError::Forbidden)));
+        assert!(matches!(resolve(&root, "/sub/inside.txt").await, Ok(p) if p == root.join("file.txt")));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
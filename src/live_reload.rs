@@ -0,0 +1,159 @@
+use std::{path::{Path, PathBuf}, time::Duration};
+use bytes::Bytes;
+use futures_util::{stream, StreamExt};
+use http_body_util::{BodyExt, StreamBody};
+use hyper::{body::Frame, header::{CACHE_CONTROL, CONTENT_TYPE, SERVER}, Response, StatusCode};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::{broadcast::{self, error::RecvError}, mpsc};
+use crate::{BoxBodyResponse, SERVER_NAME_HEADER, SHUTDOWN, TIMEOUTS};
+
+
+/// Server-Sent Events endpoint the injected script listens to
+pub const ENDPOINT: &str = "/.live-reload";
+
+/// Saving a file usually fires several events, they're sent to the browsers as one change
+const DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// Comment sent on quiet streams, more often if needed so the `--send-timeout` doesn't close them
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+const SCRIPT: &str = r#"<script>
+(() => {
+    let connected = false
+    const events = new EventSource('/.live-reload')
+    // Reconnected after a server restart, the files may have changed meanwhile
+    events.onopen = () => {
+        if (connected) location.reload()
+        connected = true
+    }
+    events.addEventListener('reload', () => location.reload())
+    events.addEventListener('css', () => {
+        document.querySelectorAll('link[rel="stylesheet"]').forEach(link => {
+            const url = new URL(link.href)
+            if (url.origin !== location.origin) return
+            url.searchParams.set('live-reload', Date.now())
+            link.href = url.href
+        })
+    })
+})()
+</script>"#;
+
+#[derive(Clone, Copy)]
+enum Change {
+    /// Only stylesheets changed, they're swapped without reloading the page
+    Css,
+    Reload,
+}
+
+/// `--live-reload`, watches the served dirs and notifies the open pages of the changes
+pub struct LiveReload {
+    changes: broadcast::Sender<Change>,
+    /// Stops watching when dropped
+    _watcher: RecommendedWatcher,
+}
+
+
+/// Hidden files, editor swap files and backups (`.app.js.swp`, `app.js~`)
+fn is_ignored(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with('.') || name.ends_with('~'))
+}
+
+fn is_css(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("css"))
+}
+
+/// Adds the live reload script before `</body>`, or at the end if there's none
+pub fn inject(html: &[u8]) -> Vec<u8> {
+    let end = html
+        .windows(7)
+        .rposition(|tag| tag.eq_ignore_ascii_case(b"</body>"))
+        .unwrap_or(html.len());
+
+    let mut injected = Vec::with_capacity(html.len() + SCRIPT.len());
+    injected.extend_from_slice(&html[..end]);
+    injected.extend_from_slice(SCRIPT.as_bytes());
+    injected.extend_from_slice(&html[end..]);
+    injected
+}
+
+/// Suffix of the ETag of the pages with the script, so caches don't mix them up with the files on disk
+pub const ETAG_SUFFIX: &str = "lr";
+
+impl LiveReload {
+    /// Watches the dirs recursively, has to be called inside the tokio runtime
+    pub fn watch<'a>(dirs: impl IntoIterator<Item = &'a Path>) -> notify::Result<Self> {
+        let (events_tx, mut events_rx) = mpsc::unbounded_channel::<PathBuf>();
+
+        // Called from the watcher thread
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            let Ok(event) = event else {
+                return;
+            };
+            if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)) {
+                for path in event.paths.into_iter().filter(|p| !is_ignored(p)) {
+                    let _ = events_tx.send(path);
+                }
+            }
+        })?;
+
+        for dir in dirs {
+            watcher.watch(dir, RecursiveMode::Recursive)?;
+        }
+
+        let (changes, _) = broadcast::channel(16);
+        let sender = changes.clone();
+        tokio::spawn(async move {
+            while let Some(path) = events_rx.recv().await {
+                let mut css_only = is_css(&path);
+
+                tokio::time::sleep(DEBOUNCE).await;
+                while let Ok(path) = events_rx.try_recv() {
+                    css_only &= is_css(&path);
+                }
+
+                let _ = sender.send(if css_only { Change::Css } else { Change::Reload });
+            }
+        });
+
+        Ok(Self { changes, _watcher: watcher })
+    }
+
+    /// Event stream of the changes, open until the client leaves or the server shuts down
+    pub fn events(&self) -> BoxBodyResponse {
+        let changes = self.changes.subscribe();
+        let shutdown = SHUTDOWN.get().unwrap().clone();
+        let keep_alive = match TIMEOUTS.get().and_then(|timeouts| timeouts.send) {
+            Some(send_timeout) => KEEP_ALIVE.min(send_timeout / 2),
+            None => KEEP_ALIVE,
+        };
+
+        let events = stream::unfold((changes, shutdown), move |(mut changes, shutdown)| async move {
+            let message = tokio::select! {
+                _ = shutdown.cancelled() => return None,
+                change = changes.recv() => match change {
+                    Ok(Change::Css) => "event: css\ndata: css\n\n",
+                    // Missed changes, reloading covers all of them
+                    Ok(Change::Reload) | Err(RecvError::Lagged(_)) => "event: reload\ndata: reload\n\n",
+                    Err(RecvError::Closed) => return None,
+                },
+                _ = tokio::time::sleep(keep_alive) => ": keep-alive\n\n",
+            };
+            Some((message, (changes, shutdown)))
+        });
+
+        // Browsers reconnect a second after the server comes back
+        let body = stream::once(async { "retry: 1000\n\n" })
+            .chain(events)
+            .map(|message| Ok(Frame::data(Bytes::from_static(message.as_bytes()))));
+
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(SERVER, SERVER_NAME_HEADER)
+            .header(CONTENT_TYPE, "text/event-stream")
+            .header(CACHE_CONTROL, "no-cache")
+            .body(BodyExt::boxed(StreamBody::new(body)))
+            .unwrap()
+    }
+}
@@ -1,10 +1,82 @@
 
+use std::io::{self, ErrorKind};
 use bytes::Bytes;
 use http_body_util::{BodyExt, Full};
-use hyper::{Response, StatusCode, header::{CONTENT_LENGTH, CONTENT_TYPE, SERVER}};
-use crate::{BoxBodyResponse, SERVER_NAME_HEADER};
+use hyper::{Response, StatusCode, header::{ALLOW, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, LAST_MODIFIED, RETRY_AFTER, SERVER, WWW_AUTHENTICATE}};
+use crate::{validators::{http_date, Validators}, BoxBodyResponse, SERVER_NAME_HEADER};
 
+/// Methods the server handles, everything else gets a 405
+pub const ALLOWED_METHODS: &str = "GET, HEAD, OPTIONS";
  
+/// HTTP status code 204, answers `OPTIONS` requests
+pub fn options() -> BoxBodyResponse {
+    let body = Full::new(Bytes::new())
+        .map_err(|never| match never {})
+        .boxed();
+
+    Response::builder()
+        .header(SERVER, SERVER_NAME_HEADER)
+        .header(ALLOW, ALLOWED_METHODS)
+        .status(StatusCode::NO_CONTENT)
+        .body(body)
+        .unwrap()
+}
+
+/// HTTP status code 405
+pub fn method_not_allowed() -> BoxBodyResponse {
+    let body = Full::new("405 Method Not Allowed".into())
+        .map_err(|never| match never {})
+        .boxed();
+
+    Response::builder()
+        .header(SERVER, SERVER_NAME_HEADER)
+        .header(ALLOW, ALLOWED_METHODS)
+        .status(StatusCode::METHOD_NOT_ALLOWED)
+        .body(body)
+        .unwrap()
+}
+
+
+/// HTTP status code 401, asks the browser for the credentials
+pub fn unauthorized() -> BoxBodyResponse {
+    let body = Full::new("401 Unauthorized".into())
+        .map_err(|never| match never {})
+        .boxed();
+
+    Response::builder()
+        .header(SERVER, SERVER_NAME_HEADER)
+        .header(WWW_AUTHENTICATE, r#"Basic realm="RustyPyserver", charset="UTF-8""#)
+        .status(StatusCode::UNAUTHORIZED)
+        .body(body)
+        .unwrap()
+}
+
+/// HTTP status code 403
+pub fn forbidden() -> BoxBodyResponse {
+    let body = Full::new("403 Forbidden".into())
+        .map_err(|never| match never {})
+        .boxed();
+
+    Response::builder()
+        .header(SERVER, SERVER_NAME_HEADER)
+        .status(StatusCode::FORBIDDEN)
+        .body(body)
+        .unwrap()
+}
+
+/// HTTP status code 410, used for expired share links
+pub fn gone() -> BoxBodyResponse {
+    let body = Full::new("410 Gone".into())
+        .map_err(|never| match never {})
+        .boxed();
+
+    Response::builder()
+        .header(SERVER, SERVER_NAME_HEADER)
+        .status(StatusCode::GONE)
+        .body(body)
+        .unwrap()
+}
+
 /// HTTP status code 404
 pub fn not_found() -> BoxBodyResponse {
     let body = Full::new("404 Not Found".into())
@@ -18,19 +90,102 @@ pub fn not_found() -> BoxBodyResponse {
         .unwrap()
 }
 
+/// HTTP status code 429, `retry_after` is in seconds
+pub fn too_many_requests(retry_after: u64) -> BoxBodyResponse {
+    let body = Full::new("429 Too Many Requests".into())
+        .map_err(|never| match never {})
+        .boxed();
+
+    Response::builder()
+        .header(SERVER, SERVER_NAME_HEADER)
+        .header(RETRY_AFTER, retry_after)
+        .status(StatusCode::TOO_MANY_REQUESTS)
+        .body(body)
+        .unwrap()
+}
+
+/// HTTP status code 503, `retry_after` is in seconds
+pub fn service_unavailable(retry_after: u64) -> BoxBodyResponse {
+    let body = Full::new("503 Service Unavailable".into())
+        .map_err(|never| match never {})
+        .boxed();
+
+    Response::builder()
+        .header(SERVER, SERVER_NAME_HEADER)
+        .header(RETRY_AFTER, retry_after)
+        .status(StatusCode::SERVICE_UNAVAILABLE)
+        .body(body)
+        .unwrap()
+}
+
+/// HTTP status code 416
+pub fn range_not_satisfiable(file_len: u64) -> BoxBodyResponse {
+    let body = Full::new("416 Range Not Satisfiable".into())
+        .map_err(|never| match never {})
+        .boxed();
+
+    Response::builder()
+        .header(SERVER, SERVER_NAME_HEADER)
+        .header(CONTENT_RANGE, format!("bytes */{file_len}"))
+        .status(StatusCode::RANGE_NOT_SATISFIABLE)
+        .body(body)
+        .unwrap()
+}
+
+/// HTTP status code 304, carries the validators but no body
+pub fn not_modified(validators: &Validators) -> BoxBodyResponse {
+    let body = Full::new(Bytes::new())
+        .map_err(|never| match never {})
+        .boxed();
+
+    let mut response = Response::builder()
+        .header(SERVER, SERVER_NAME_HEADER)
+        .header(ETAG, &validators.etag)
+        .status(StatusCode::NOT_MODIFIED);
+
+    if let Some(last_modified) = validators.last_modified {
+        response = response.header(LAST_MODIFIED, http_date(last_modified));
+    }
+
+    response.body(body).unwrap()
+}
+
+/// HTTP status code 412
+pub fn precondition_failed() -> BoxBodyResponse {
+    let body = Full::new("412 Precondition Failed".into())
+        .map_err(|never| match never {})
+        .boxed();
+
+    Response::builder()
+        .header(SERVER, SERVER_NAME_HEADER)
+        .status(StatusCode::PRECONDITION_FAILED)
+        .body(body)
+        .unwrap()
+}
+
 /// HTTP status code 500
-// pub fn error_response(msg: impl Into<Bytes>) -> BoxBodyResponse {
-//     let body = Full::new(msg.into())
-//         .map_err(|never| match never {})
-//         .boxed();
-
-//     Response::builder()
-//         .status(StatusCode::INTERNAL_SERVER_ERROR)
-//         .body(body)
-//         .unwrap()
-// }
-
-pub fn index(index: impl Into<Bytes>) -> BoxBodyResponse {
+pub fn error_response() -> BoxBodyResponse {
+    let body = Full::new("500 Internal Server Error".into())
+        .map_err(|never| match never {})
+        .boxed();
+
+    Response::builder()
+        .header(SERVER, SERVER_NAME_HEADER)
+        .status(StatusCode::INTERNAL_SERVER_ERROR)
+        .body(body)
+        .unwrap()
+}
+
+/// 404 for missing files, 403 when they can't be read, 500 for anything else
+pub fn io_error_response(err: &io::Error) -> BoxBodyResponse {
+    match err.kind() {
+        ErrorKind::NotFound | ErrorKind::NotADirectory => not_found(),
+        ErrorKind::PermissionDenied => forbidden(),
+        _ => error_response(),
+    }
+}
+
+pub fn index(index: impl Into<Bytes>, etag: &str) -> BoxBodyResponse {
     let bytes: Bytes = index.into();
     let bytes_len = bytes.len();
 
@@ -43,6 +198,7 @@ pub fn index(index: impl Into<Bytes>) -> BoxBodyResponse {
         .header(CONTENT_TYPE, "text/html")
         .header(SERVER, SERVER_NAME_HEADER)
         .header(CONTENT_LENGTH, bytes_len)
+        .header(ETAG, etag)
         .body(body)
         .unwrap()
 }
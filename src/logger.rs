@@ -10,10 +10,7 @@ macro_rules! ___log_msg {
             // clear line and Print stats line
             crossterm::terminal::Clear(crossterm::terminal::ClearType::CurrentLine),
             crossterm::style::SetForegroundColor(crossterm::style::Color::DarkGrey),
-            crossterm::style::Print(format_args!(
-                "Total requests: {} | Current requests: {} | Bytes/s: {}/s",
-                $stats.total_requests, $stats.requests, format_file_size($stats.bandwith.get_bandwith())
-            )),
+            crossterm::style::Print($stats.line()),
             crossterm::style::ResetColor,
 
             // move 1 up and clear the log line
@@ -30,15 +27,25 @@ macro_rules! ___log_msg {
     }};
 }
 
+// Before the logger is running (while parsing the CLI args) errors go straight to stderr
 macro_rules! print_error {
     ($exit_num:expr ; $($arg:tt)*) => {{
-        if let Some(tx) = crate::logger::LOGGER.get() {
-            let _ = tx.send(crate::logger::LogMsg::Error(format_args!($($arg)*).to_string(), true, $exit_num));
+        match crate::logger::LOGGER.get() {
+            Some(tx) => {
+                let _ = tx.send(crate::logger::LogMsg::Error(format_args!($($arg)*).to_string(), true, $exit_num));
+            }
+            None => {
+                eprintln!("⚠️ {}", format_args!($($arg)*));
+                std::process::exit($exit_num);
+            }
         }
     }};
     ($($arg:tt)*) => {{
-        if let Some(tx) = crate::logger::LOGGER.get() {
-            let _ = tx.send(crate::logger::LogMsg::Error(format_args!($($arg)*).to_string(), false, 0));
+        match crate::logger::LOGGER.get() {
+            Some(tx) => {
+                let _ = tx.send(crate::logger::LogMsg::Error(format_args!($($arg)*).to_string(), false, 0));
+            }
+            None => eprintln!("⚠️ {}", format_args!($($arg)*)),
         }
     }};
 }
@@ -63,10 +70,38 @@ macro_rules! print_request {
 
 #[derive(Default)]
 pub struct Stats {
+    pub connections: u32,
+    pub rejected: u32,
+    pub throttled: u32,
+    pub queued: u32,
     pub requests: u32,
     pub total_requests: u32,
+    pub bytes_sent: u64,
+    pub shutting_down: bool,
     pub bandwith: BandwithTracker,
 }
+impl Stats {
+    pub fn line(&mut self) -> String {
+        let mut line = format!(
+            "Total requests: {} | Current requests: {} | Connections: {} | Bytes/s: {}/s",
+            self.total_requests, self.requests, self.connections, format_file_size(self.bandwith.get_bandwith())
+        );
+
+        if self.shutting_down {
+            line.push_str(&format!(" | Shutting down: {} connections left", self.connections));
+        }
+        if self.queued > 0 {
+            line.push_str(&format!(" | Queued: {}", self.queued));
+        }
+        if self.rejected > 0 {
+            line.push_str(&format!(" | Rejected: {}", self.rejected));
+        }
+        if self.throttled > 0 {
+            line.push_str(&format!(" | Throttled: {}", self.throttled));
+        }
+        line
+    }
+}
 
 #[derive(Default)]
 pub struct BandwithTracker {
@@ -97,13 +132,22 @@ pub enum StatsMsg {
     Refresh,
     SendedBytes(u32),
     NewRequest,
-    RequestEnded
+    RequestEnded,
+    NewConnection,
+    ConnectionEnded,
+    ConnectionRejected,
+    Throttled,
+    ConnectionQueued,
+    ConnectionDequeued,
+    ShuttingDown,
 }
 pub enum LogMsg {
     Error(String, bool, i32),
     Info(String),
     Request(String),
     Stats(StatsMsg),
+    /// Prints the summary and exits the process
+    Exit,
 }
 
 pub static LOGGER: OnceLock<mpsc::Sender<LogMsg>> = OnceLock::new();
@@ -115,6 +159,40 @@ pub fn update_stats(msg: StatsMsg) {
     }
 }
 
+/// Counts a request as active until it's dropped (alongside the response body)
+pub struct RequestGuard;
+impl RequestGuard {
+    pub fn new() -> Self {
+        update_stats(StatsMsg::NewRequest);
+        Self
+    }
+}
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        update_stats(StatsMsg::RequestEnded);
+    }
+}
+
+/// Prints the final summary (total requests, bytes sent and uptime) and exits
+pub fn exit_with_summary() {
+    match LOGGER.get() {
+        Some(tx) => {
+            let _ = tx.send(LogMsg::Exit);
+        }
+        None => std::process::exit(0),
+    }
+}
+
+/// `1h 02m 03s`
+fn format_uptime(uptime: Duration) -> String {
+    let secs = uptime.as_secs();
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{s}s"),
+        (0, m, s) => format!("{m}m {s:02}s"),
+        (h, m, s) => format!("{h}h {m:02}m {s:02}s"),
+    }
+}
+
 fn print_stats(stats: &mut Stats) {
     let _ = execute!(std::io::stdout(), 
 
@@ -125,29 +203,24 @@ fn print_stats(stats: &mut Stats) {
         SetForegroundColor(Color::DarkGrey),
 
         // Print updated stats && move 1 down
-        Print(format_args!(
-            "Total requests: {} | Current requests: {} | Bytes/s: {}/s\n",
-            stats.total_requests, stats.requests, format_file_size(stats.bandwith.get_bandwith())
-        )),
+        Print(format_args!("{}\n", stats.line())),
         ResetColor,
     );
 }
 
 
 fn log_request(file: &mut Option<File>, request: &String) {
-    unsafe {
-        if let Some(file_path) = LOG_FILE.as_ref() {
-            if !file_path.exists() || file.is_none() {
-                let new = File::create(file_path);
-                if new.is_err() {
-                    print_error!("Failed to create log file: {:?}", new);
-                }
-                *file = new.ok();
+    if let Some(file_path) = LOG_FILE.get() {
+        if !file_path.exists() || file.is_none() {
+            let new = File::create(file_path);
+            if new.is_err() {
+                print_error!("Failed to create log file: {:?}", new);
             }
+            *file = new.ok();
+        }
 
-            if let Some(file) = file.as_mut() {
-                let _ = writeln!(file, "{}", request);
-            }
+        if let Some(file) = file.as_mut() {
+            let _ = writeln!(file, "{}", request);
         }
     }
 }
@@ -155,7 +228,7 @@ fn log_request(file: &mut Option<File>, request: &String) {
 
 pub fn init_stats_logger() {
     let (tx, rx) = mpsc::channel();
-    if let Err(_) = LOGGER.set(tx.clone()) {
+    if LOGGER.set(tx.clone()).is_err() {
         eprintln!("Failed to set the logger");
     }
 
@@ -167,6 +240,7 @@ pub fn init_stats_logger() {
     });
 
     std::thread::spawn(move || {
+        let started = Instant::now();
         let mut stats = Stats::default();
         let mut logs_file = None;
 
@@ -175,14 +249,15 @@ pub fn init_stats_logger() {
                 LogMsg::Error(e, exit, code) => {
                     log_request(&mut logs_file, &e);
 
-                    let r = ___log_msg!(
+                    let _ = ___log_msg!(
                         stats;
                         crossterm::style::SetForegroundColor(crossterm::style::Color::Red),
                         crossterm::style::Print("⚠️ "),  
                         crossterm::style::Print(e),
                     );
+                    // Exits even if stdout is gone, main waits for it after fatal errors
                     if exit {
-                        let _ = r.and_then::<(), _>(|_| std::process::exit(code));
+                        std::process::exit(code);
                     }
                 }
                 LogMsg::Info(i) => {
@@ -203,6 +278,21 @@ pub fn init_stats_logger() {
                         crossterm::style::Print(r),
                     );                
                 },
+                LogMsg::Exit => {
+                    let summary = format!(
+                        "Total requests: {} | Bytes sent: {} | Uptime: {}",
+                        stats.total_requests, format_file_size(stats.bytes_sent), format_uptime(started.elapsed())
+                    );
+                    log_request(&mut logs_file, &summary);
+
+                    // Replaces the stats line, so the terminal is left clean
+                    let _ = execute!(std::io::stdout(),
+                        cursor::MoveUp(1),
+                        Clear(ClearType::CurrentLine),
+                        Print(format_args!("{summary}\n")),
+                    );
+                    std::process::exit(0);
+                }
                 LogMsg::Stats(s) => match s {
                     StatsMsg::NewRequest => {
                         stats.requests += 1;
@@ -213,9 +303,35 @@ pub fn init_stats_logger() {
                             stats.requests -= 1;
                         }
                     },
+                    StatsMsg::NewConnection => {
+                        stats.connections += 1;
+                    }
+                    StatsMsg::ConnectionEnded => {
+                        if stats.connections > 0 {
+                            stats.connections -= 1;
+                        }
+                    }
+                    StatsMsg::ConnectionRejected => {
+                        stats.rejected += 1;
+                    }
+                    StatsMsg::ConnectionQueued => {
+                        stats.queued += 1;
+                    }
+                    StatsMsg::ConnectionDequeued => {
+                        if stats.queued > 0 {
+                            stats.queued -= 1;
+                        }
+                    }
+                    StatsMsg::Throttled => {
+                        stats.throttled += 1;
+                    }
                     StatsMsg::SendedBytes(b) => {
                         stats.bandwith.add_bytes(b);
+                        stats.bytes_sent += b as u64;
                     },
+                    StatsMsg::ShuttingDown => {
+                        stats.shutting_down = true;
+                    }
                     StatsMsg::Refresh => {
                         print_stats(&mut stats);
                     },
@@ -1,31 +1,55 @@
 use bytes::Bytes;
 use futures_util::TryStreamExt;
 use http_body_util::combinators::BoxBody;
-use http_body_util::{BodyExt, StreamBody};
-use hyper::header::{CONTENT_LENGTH, CONTENT_TYPE, SERVER};
-use hyper_util::rt::TokioIo;
-use local_response::{index, not_found};
-use logger::{update_stats, StatsMsg};
+use http_body_util::{BodyExt, Empty, StreamBody};
+use hyper::header::{HeaderValue, ACCEPT_RANGES, CACHE_CONTROL, ORIGIN, CONNECTION, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, LAST_MODIFIED, RANGE, SERVER, VARY};
+use hyper_util::{rt::{TokioExecutor, TokioIo, TokioTimer}, server::conn::auto};
+use jail::JailError;
+use sites::{Site, Sites};
+use auth::Auth;
+use ip_filter::IpFilter;
+use rate_limit::RateLimiter;
+use conn_limit::ConnectionLimit;
+use activity::{Activity, ActivityStream, InFlight, Timeouts};
+use compress::Compression;
+use cors::Cors;
+use headers::CustomHeaders;
+use cache_control::CacheRule;
+use error_page::ErrorPages;
+use live_reload::LiveReload;
+use throttle::{Bandwidth, Throttle};
+use share::{ShareError, ShareGrant, ShareKey};
+use local_response::{error_response, forbidden, io_error_response, gone, index, method_not_allowed, not_found, not_modified, options, precondition_failed, range_not_satisfiable, service_unavailable, too_many_requests, unauthorized};
+use logger::{update_stats, RequestGuard, StatsMsg};
+use range::RangeRequest;
 use reader_inspector::ReaderInspector;
-use tokio_util::io::ReaderStream;
+use validators::{Precondition, Validators};
+use tokio_util::{io::ReaderStream, sync::CancellationToken, task::TaskTracker};
 use hyper::{
     body::Frame,
-    server::conn::http1,
     service::service_fn,
     Result as HyperResult,
     body::Incoming,
+    Method,
     Request,
     Response,
     StatusCode,
+    Version,
 };
 use tokio::{
     task::JoinHandle,
     fs::{self, File},
+    io::{AsyncRead, AsyncWrite},
     net::TcpListener,
 };
 use std::{
+    borrow::Cow,
+    fs::Metadata,
+    io::{Cursor, ErrorKind},
     net::SocketAddr,
     path::{Path, PathBuf},
+    sync::OnceLock,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 use crate::{
     cli::CliArgs,
@@ -39,42 +63,237 @@ mod html;
 mod cli;
 mod dir_to_zip;
 mod local_response;
+mod range;
+mod validators;
+mod tls;
+mod jail;
+mod mounts;
+mod sites;
+mod auth;
+mod share;
+mod ip_filter;
+mod rate_limit;
+mod conn_limit;
+mod activity;
+mod shutdown;
+mod compress;
+mod cors;
+mod headers;
+mod cache_control;
+mod error_page;
+mod live_reload;
+mod throttle;
 
 type BoxBodyResponse = Response<BoxBody<Bytes, std::io::Error>>;
 
-static mut SHOW_HTML: bool = false;
-static mut SPA_FILE: Option<PathBuf> = None;
-static mut LOG_FILE: Option<PathBuf> = None;
+static ETAG_CONTENT_HASH: OnceLock<bool> = OnceLock::new();
+static SITES: OnceLock<Sites> = OnceLock::new();
+static AUTH: OnceLock<Auth> = OnceLock::new();
+static SHARE_KEY: OnceLock<ShareKey> = OnceLock::new();
+static IP_FILTER: OnceLock<IpFilter> = OnceLock::new();
+static RATE_LIMITER: OnceLock<RateLimiter> = OnceLock::new();
+static CONNECTION_LIMIT: OnceLock<ConnectionLimit> = OnceLock::new();
+static TIMEOUTS: OnceLock<Timeouts> = OnceLock::new();
+static COMPRESSION: OnceLock<Compression> = OnceLock::new();
+static CORS: OnceLock<Cors> = OnceLock::new();
+static CUSTOM_HEADERS: OnceLock<CustomHeaders> = OnceLock::new();
+static CACHE_RULES: OnceLock<Vec<CacheRule>> = OnceLock::new();
+/// `--spa-exclude` prefixes, never answered with the SPA file
+static SPA_EXCLUDE: OnceLock<Vec<String>> = OnceLock::new();
+static ERROR_PAGES: OnceLock<ErrorPages> = OnceLock::new();
+static LIVE_RELOAD: OnceLock<LiveReload> = OnceLock::new();
+/// Cancelled on the first SIGINT / SIGTERM
+static SHUTDOWN: OnceLock<CancellationToken> = OnceLock::new();
+/// Bytes per second allowed for each connection
+static LIMIT_RATE: OnceLock<u64> = OnceLock::new();
+static GLOBAL_LIMIT_RATE: OnceLock<Bandwidth> = OnceLock::new();
+static SHARE_ONLY: OnceLock<bool> = OnceLock::new();
+static SHOW_SIDECARS: OnceLock<bool> = OnceLock::new();
+static NO_CACHE: OnceLock<bool> = OnceLock::new();
+static LOG_FILE: OnceLock<PathBuf> = OnceLock::new();
 const SERVER_NAME_HEADER: &str = "RustyPyserver";
 const CHUNK_SIZE: usize = 32 * 1024;
+/// Streams a single HTTP/2 connection can have open, otherwise one connection could run
+/// as many requests at once as the whole --max-connections limit
+const MAX_CONCURRENT_STREAMS: u32 = 32;
 
 
 #[tokio::main]
 async fn main() {
+    let cli_args = CliArgs::parse();
+
+    let share_key = cli_args.share_secret.as_ref().map(|secret| ShareKey::new(secret.as_bytes()));
+
+    if let Some(share) = cli_args.share {
+        let Some(share_key) = share_key else {
+            print_error!(1; "[share] --share-secret is required to sign the link");
+            return;
+        };
+
+        let expires = SystemTime::now() + share.expires;
+        let expires_unix = expires.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+        let base_url = share.base_url.unwrap_or_else(|| {
+            let host = tls::lan_ip().map(|ip| ip.to_string()).unwrap_or_else(|| "localhost".to_string());
+            match share.port {
+                80 => format!("http://{host}"),
+                port => format!("http://{host}:{port}"),
+            }
+        });
+
+        println!("{}{}", base_url.trim_end_matches('/'), share_key.link(&share.path, expires_unix, share.max_downloads));
+        println!("Expires: {}", chrono::DateTime::<chrono::Local>::from(expires).format("%d-%m-%Y %H:%M:%S"));
+        return;
+    }
+
     // Make space for the logger msgs
     println!();
     logger::init_stats_logger();
+    let _ = ETAG_CONTENT_HASH.set(cli_args.etag_hash);
+    let _ = SHARE_ONLY.set(cli_args.share_only);
+    let _ = SHOW_SIDECARS.set(cli_args.show_sidecars);
+    let _ = NO_CACHE.set(cli_args.no_cache);
+    if let Some(share_key) = share_key {
+        let _ = SHARE_KEY.set(share_key);
+    }
+    if let Some(log_file) = cli_args.log_file {
+        let _ = LOG_FILE.set(log_file);
+    }
 
-    let cli_args = CliArgs::parse();
-    unsafe { 
-        SHOW_HTML = cli_args.show_html;
-        LOG_FILE = cli_args.log_file;
+    let default_site = Site::new("", &cli_args.root, cli_args.mounts, cli_args.show_html, cli_args.spa_file);
+    let vhosts = cli_args.vhosts
+        .into_iter()
+        .map(|v| {
+            let site = Site::new(&format!("[{}] ", v.host), &v.root, Vec::new(), v.show_html, v.spa_file)?;
+            Ok((v.host, site))
+        })
+        .collect::<Result<_, String>>();
+
+    // Stops before binding any listener, the logger prints the error and exits
+    let (default_site, vhosts) = match (default_site, vhosts) {
+        (Ok(default_site), Ok(vhosts)) => (default_site, vhosts),
+        (Err(e), _) | (_, Err(e)) => {
+            print_error!(1; "{e}");
+            return std::future::pending().await;
+        }
     };
 
+    let sites = SITES.get_or_init(|| Sites { default: default_site, vhosts });
+
+    if cli_args.live_reload {
+        let dirs = sites.vhosts.values().chain([&sites.default]).flat_map(|site| site.mounts.dirs());
+        match LiveReload::watch(dirs) {
+            Ok(live_reload) => {
+                print_info!("Live reload enabled, watching the served directories");
+                let _ = LIVE_RELOAD.set(live_reload);
+            }
+            Err(e) => {
+                print_error!(1; "[--live-reload] Failed to watch the served directories: {e}");
+                return std::future::pending().await;
+            }
+        }
+    }
+
+    if !cli_args.allow.is_empty() || !cli_args.deny.is_empty() {
+        let _ = IP_FILTER.set(IpFilter { allow: cli_args.allow, deny: cli_args.deny });
+    }
+
+    if cli_args.rate_limit.is_some() || cli_args.max_conns_per_ip.is_some() {
+        let _ = RATE_LIMITER.set(RateLimiter::new(cli_args.rate_limit, cli_args.rate_burst, cli_args.max_conns_per_ip));
+    }
+
+    let shutdown = SHUTDOWN.get_or_init(CancellationToken::new);
+    let connections = TaskTracker::new();
+
+    let _ = TIMEOUTS.set(Timeouts {
+        header: cli_args.header_timeout,
+        idle: cli_args.idle_timeout,
+        send: cli_args.send_timeout,
+    });
+
+    if let Some(cors) = cli_args.cors {
+        let _ = CORS.set(cors);
+    }
+
+    if !cli_args.headers.is_empty() {
+        let _ = CUSTOM_HEADERS.set(CustomHeaders(cli_args.headers));
+    }
+
+    if !cli_args.spa_exclude.is_empty() {
+        let _ = SPA_EXCLUDE.set(cli_args.spa_exclude);
+    }
+
+    if !cli_args.error_pages.is_empty() {
+        let _ = ERROR_PAGES.set(ErrorPages(cli_args.error_pages));
+    }
+
+    if !cli_args.cache_rules.is_empty() {
+        let _ = CACHE_RULES.set(cli_args.cache_rules);
+    }
 
-    // If the SPA file exists, set it to the global variable
-    if let Some(spa_file_path) = cli_args.spa_file {
-        if spa_file_path.exists() {
-            print_info!("SPA file set to: {}", spa_file_path.display());
-            unsafe { SPA_FILE = Some(spa_file_path) };
-        } else {
-            print_error!(0; "[--spa] File {} does not exist in the current dir", spa_file_path.display());
+    if !cli_args.compression.is_empty() {
+        let _ = COMPRESSION.set(Compression {
+            encodings: cli_args.compression,
+            min_size: cli_args.compression_min_size,
+        });
+    }
+
+    if let Some(max_connections) = cli_args.max_connections {
+        let _ = CONNECTION_LIMIT.set(ConnectionLimit::new(max_connections, cli_args.queue_size, cli_args.queue_timeout));
+    }
+
+    if let Some(rate) = cli_args.limit_rate {
+        let _ = LIMIT_RATE.set(rate);
+    }
+    if let Some(rate) = cli_args.global_limit_rate {
+        let _ = GLOBAL_LIMIT_RATE.set(Bandwidth::new(rate));
+    }
+
+    if !cli_args.auth.is_empty() || cli_args.auth_file.is_some() {
+        match Auth::load(&cli_args.auth, cli_args.auth_file.as_deref()) {
+            Ok(auth) => {
+                let _ = AUTH.set(auth);
+            }
+            // Stops before binding any listener, the logger prints the error and exits
+            Err(e) => {
+                print_error!(1; "{e:#}");
+                return std::future::pending().await;
+            }
         }
     }
 
-    let mut listeners = Vec::with_capacity(cli_args.listen_ports.len());
+    let tls_acceptor = match &cli_args.tls {
+        Some(source) => match tls::acceptor(source) {
+            Ok(acceptor) => Some(acceptor),
+            Err(e) => {
+                print_error!(1; "[--tls] {e:#}");
+                return std::future::pending().await;
+            }
+        },
+        None => None,
+    };
+
+    if tls_acceptor.is_none() && !cli_args.tls_ports.is_empty() {
+        print_error!("[--tls-ports] Ignored, a certificate is required (--tls-cert / --tls-self-signed)");
+    }
+
+    let mut ports = cli_args.listen_ports.clone();
+    if tls_acceptor.is_some() {
+        ports.extend(&cli_args.tls_ports);
+    }
+
+    let mut listeners = Vec::with_capacity(ports.len());
+
+    for port in ports {
+        // Without --tls-ports every port serves HTTPS
+        let tls_acceptor = tls_acceptor
+            .clone()
+            .filter(|_| cli_args.tls_ports.is_empty() || cli_args.tls_ports.contains(&port));
+
+        let scheme = match tls_acceptor {
+            Some(_) => "https",
+            None => "http",
+        };
 
-    for port in cli_args.listen_ports {
         let addr = match cli_args.only_localhost {
             true => format!("localhost:{}", port),
             false => format!("0.0.0.0:{}", port),
@@ -89,28 +308,73 @@ async fn main() {
         };
 
         match cli_args.only_localhost {
-            true => print_info!("Listening on http://localhost:{}", port),
-            false => print_info!("Listening on http://localhost:{} and http://{}", port, addr)
+            true => print_info!("Listening on {scheme}://localhost:{}", port),
+            false => print_info!("Listening on {scheme}://localhost:{} and {scheme}://{}", port, addr)
         };
 
+        let connections = connections.clone();
         let handle: JoinHandle<anyhow::Result<()>> = tokio::spawn(async move {
             loop {
-                let Ok((stream, _)) = listener.accept().await else {
+                let accepted = tokio::select! {
+                    _ = shutdown.cancelled() => return Ok(()),
+                    accepted = listener.accept() => accepted,
+                };
+                let Ok((stream, _)) = accepted else {
                     continue;
                 };
 
                 let from_who = stream.peer_addr().unwrap();
-                let io = TokioIo::new(stream);
+
+                // Rejected peers are dropped before reaching hyper
+                if IP_FILTER.get().is_some_and(|filter| !filter.is_allowed(from_who.ip())) {
+                    print_error!("{} -> Connection rejected by the --allow / --deny rules", from_who);
+                    update_stats(StatsMsg::ConnectionRejected);
+                    continue;
+                }
+
+                // Connections over the --max-conns-per-ip limit are still served, but only with 429 responses
+                let (slot, mut refusal) = match RATE_LIMITER.get().map(|limiter| limiter.connect(from_who.ip())) {
+                    Some(None) => (None, Some(Refusal::TooManyRequests)),
+                    Some(slot) => (slot, None),
+                    None => (None, None),
+                };
+
+                let tls_acceptor = tls_acceptor.clone();
                 
-                tokio::spawn(async move {
-                    update_stats(StatsMsg::NewRequest);
-                    if let Err(err) = http1::Builder::new()
-                        .serve_connection(io, service_fn(|req| handle_response(req, from_who, port)))
-                        .await
-                    {
-                        print_error!("{} -> Failed to serve connection: {:?}", from_who, err);
+                connections.spawn(async move {
+                    let _slot = slot;
+
+                    // Waits in the queue when --max-connections is reached, refused with 503 if it's full
+                    let _permit = match (CONNECTION_LIMIT.get(), refusal) {
+                        (Some(limit), None) => {
+                            let permit = limit.admit().await;
+                            if permit.is_none() {
+                                update_stats(StatsMsg::ConnectionRejected);
+                                refusal = Some(Refusal::Unavailable);
+                            }
+                            permit
+                        }
+                        _ => None,
+                    };
+
+                    match tls_acceptor {
+                        // The handshake counts towards the --header-timeout, a client that never sends
+                        // its ClientHello would hold the connection slot forever
+                        Some(acceptor) => {
+                            let handshake = acceptor.accept(stream);
+                            let handshake = match TIMEOUTS.get().unwrap().header {
+                                Some(timeout) => tokio::time::timeout(timeout, handshake).await,
+                                None => Ok(handshake.await),
+                            };
+
+                            match handshake {
+                                Ok(Ok(stream)) => serve_connection(stream, from_who, port, true, refusal).await,
+                                Ok(Err(e)) => print_error!("{} -> TLS handshake failed: {}", from_who, e),
+                                Err(_) => print_error!("{} -> Closing the connection, no TLS handshake within the --header-timeout", from_who),
+                            }
+                        }
+                        None => serve_connection(stream, from_who, port, false, refusal).await,
                     }
-                    update_stats(StatsMsg::RequestEnded);
                 });
             }
         });
@@ -118,74 +382,417 @@ async fn main() {
         listeners.push(handle);
     }
 
+    if listeners.is_empty() {
+        return;
+    }
+
+    shutdown::signal().await;
+
+    // Stops accepting, the active connections finish their responses and close
+    print_info!("Shutting down, waiting up to {:?} for the active connections (press Ctrl-C again to exit now)", cli_args.shutdown_timeout);
+    update_stats(StatsMsg::ShuttingDown);
+    shutdown.cancel();
     for handle in listeners {
         let _ = handle.await;
     }
+
+    connections.close();
+    tokio::select! {
+        _ = tokio::time::timeout(cli_args.shutdown_timeout, connections.wait()) => {}
+        _ = shutdown::signal() => {}
+    }
+
+    logger::exit_with_summary();
+    std::future::pending::<()>().await;
+}
+
+
+/// Why every request of a connection is refused without being handled
+#[derive(Clone, Copy)]
+enum Refusal {
+    /// Over the --max-conns-per-ip limit
+    TooManyRequests,
+    /// Over the --max-connections limit (and its queue)
+    Unavailable,
+}
+
+
+async fn serve_connection<S>(stream: S, who: SocketAddr, port: u16, is_tls: bool, refusal: Option<Refusal>)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    update_stats(StatsMsg::NewConnection);
+    let throttle = Throttle::for_connection(LIMIT_RATE.get().copied());
+    let timeouts = TIMEOUTS.get().unwrap();
+    let activity = Activity::new();
+
+    let mut builder = auto::Builder::new(TokioExecutor::new());
+    builder
+        .http1()
+        .timer(TokioTimer::new())
+        .header_read_timeout(timeouts.header);
+    builder
+        .http2()
+        .max_concurrent_streams(MAX_CONCURRENT_STREAMS);
+
+    // Serves HTTP/1.1 and HTTP/2 (detected by the connection preface or ALPN) on the same port
+    let service_activity = activity.clone();
+    let connection = builder.serve_connection(
+        TokioIo::new(ActivityStream::new(stream, activity.clone())),
+        service_fn(move |mut req| {
+            req.extensions_mut().insert(throttle.clone());
+            let in_flight = service_activity.request();
+            async move {
+                match refusal {
+                    Some(refusal) => Ok(refused_response(&req, refusal)),
+                    None => handle_request(req, who, port, is_tls, in_flight).await,
+                }
+            }
+        }),
+    );
+    tokio::pin!(connection);
+
+    let mut check = tokio::time::interval(Duration::from_secs(1));
+    let shutdown = SHUTDOWN.get().unwrap();
+    let mut closing = false;
+    let result = loop {
+        tokio::select! {
+            result = connection.as_mut() => break result,
+            _ = shutdown.cancelled(), if !closing => {
+                closing = true;
+                connection.as_mut().graceful_shutdown();
+            }
+            _ = check.tick() => {
+                if activity.in_flight() > 0 {
+                    // Dropping the connection drops the stalled response bodies too
+                    if timeouts.send.is_some_and(|timeout| activity.send_stalled_for() >= timeout) {
+                        print_error!("{} -> Closing the connection, the client stopped reading the response", who);
+                        break Ok(());
+                    }
+                } else if !closing && timeouts.idle.is_some_and(|timeout| activity.idle_for() >= timeout) {
+                    closing = true;
+                    connection.as_mut().graceful_shutdown();
+                }
+            }
+        }
+    };
+
+    if let Err(err) = result {
+        match err.downcast_ref::<hyper::Error>() {
+            Some(err) if err.is_timeout() => print_error!("{} -> Closing the connection, no request headers within the --header-timeout", who),
+            _ => print_error!("{} -> Failed to serve connection: {:?}", who, err),
+        }
+    }
+    update_stats(StatsMsg::ConnectionEnded);
+}
+
+
+/// 429 / 503 for the requests of a refused connection, HTTP/1 connections are closed right after it
+fn refused_response(req: &Request<Incoming>, refusal: Refusal) -> BoxBodyResponse {
+    let mut response = match refusal {
+        Refusal::TooManyRequests => too_many_requests(1),
+        Refusal::Unavailable => service_unavailable(5),
+    };
+    if req.version() < Version::HTTP_2 {
+        response.headers_mut().insert(CONNECTION, HeaderValue::from_static("close"));
+    }
+    response
+}
+
+
+/// Every request (HTTP/2 streams included) is counted until its response body is dropped
+async fn handle_request(req: Request<Incoming>, who: SocketAddr, port: u16, is_tls: bool, in_flight: InFlight) -> HyperResult<BoxBodyResponse> {
+    let guard = RequestGuard::new();
+    let is_head = req.method() == Method::HEAD;
+    let origin = req.headers().get(ORIGIN).cloned();
+    let path = CUSTOM_HEADERS.get().map(|_| urlencoding::decode(req.uri().path()).unwrap_or_default().into_owned());
+    let wants_json = error_page::wants_json(req.headers());
+    let site = SITES.get().unwrap().for_request(&req);
+    let response = handle_response(req, who, port).await?;
+    let mut response = error_page::render(response, wants_json, ERROR_PAGES.get(), site).await;
+
+    if let Some(cors) = CORS.get() {
+        cors.apply(origin.as_ref(), &mut response);
+    }
+
+    if let (Some(custom_headers), Some(path)) = (CUSTOM_HEADERS.get(), path) {
+        custom_headers.apply(&path, is_tls, response.headers_mut());
+    }
+
+    if NO_CACHE.get().copied().unwrap_or_default() {
+        response.headers_mut().insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    }
+
+    // HEAD gets the headers of the GET response (Content-Length included), the body is never read
+    if is_head {
+        response = response.map(|_| Empty::new().map_err(|never| match never {}).boxed());
+    }
+
+    Ok(response.map(|body| body
+        .map_frame(move |frame| {
+            let _ = (&guard, &in_flight);
+            frame
+        })
+        .boxed()
+    ))
 }
 
 
 async fn handle_response(req: Request<Incoming>, who: SocketAddr, port: u16) -> HyperResult<BoxBodyResponse> {
+    // Throttled before the auth checks, so credentials can't be brute forced at full speed
+    if let Some(Err(wait)) = RATE_LIMITER.get().map(|limiter| limiter.check_request(who.ip())) {
+        return Ok(too_many_requests(wait.as_secs_f64().ceil().max(1.0) as u64));
+    }
+
     let path_raw = urlencoding::decode(req.uri().path()).unwrap_or_default();
 
     let method = req.method();
-    let now = chrono::Local::now().format("%d-%m-%Y %H:%M:%S");
-    print_request!(":{port} [{now}] --> {who} --> {method} {path_raw}");
+    let version = req.version();
+    let host = sites::request_host(&req).unwrap_or_else(|| "-".to_string());
 
-    let path = match path_raw.len() {
-        1 => ".", // If the path is just '/', serve the current directory
-        _ => &path_raw[1..],
+    // If the path starts with '*', it means we want to zip the directory
+    let (is_zip, uri_path) = match path_raw.strip_prefix("/*") {
+        Some(uri_dir) => (true, uri_dir),
+        None => (false, &*path_raw),
     };
 
-    // If the path starts with '*', it means we want to zip the directory
-    if path.starts_with("*") {
-        let path = match path {
-            "*" | "*/" => ".",
-            _ => &path[2..],
+    // Share links carry their own authorization, the path inside the site follows the token
+    let (share, uri_path) = match SHARE_KEY.get().and_then(|key| key.verify(uri_path)) {
+        Some(Ok((grant, inner_path))) => (Some(grant), inner_path),
+        Some(Err(e)) => {
+            print_error!("{who} -> Rejected share link: {path_raw}");
+            return Ok(share_error_response(e));
+        }
+        None => (None, uri_path),
+    };
+
+    // Checked before touching the filesystem
+    let user = match (AUTH.get(), &share) {
+        (Some(auth), None) => auth.authenticate(req.headers()).await,
+        _ => None,
+    };
+    let client = match (&user, &share) {
+        (Some(user), _) => format!("{user}@{who}"),
+        (None, Some(_)) => format!("share@{who}"),
+        (None, None) => who.to_string(),
+    };
+
+    let now = chrono::Local::now().format("%d-%m-%Y %H:%M:%S");
+    print_request!(":{port} [{now}] --> {client} --> {host} {method} {path_raw} {version:?}");
+
+    match *method {
+        Method::GET | Method::HEAD => {}
+        // Preflights carry no credentials, so they're answered before the auth checks
+        Method::OPTIONS => return Ok(match CORS.get() {
+            Some(cors) if cors::is_preflight(&req) => cors.preflight(&req),
+            _ => options(),
+        }),
+        _ => return Ok(method_not_allowed()),
+    }
+
+    if share.is_none() && user.is_none() {
+        if AUTH.get().is_some() {
+            return Ok(unauthorized());
+        }
+        if SHARE_ONLY.get().copied().unwrap_or_default() {
+            return Ok(forbidden());
+        }
+    }
+
+    if let Some(live_reload) = LIVE_RELOAD.get() {
+        if path_raw == live_reload::ENDPOINT {
+            return Ok(live_reload.events());
+        }
+    }
+
+    let site = SITES.get().unwrap().for_request(&req);
+
+    let mounts = &site.mounts;
+
+    if is_zip {
+        let (mount, path) = mounts.find(uri_path);
+        let dir = match jail::resolve(&mount.dir, path).await {
+            Ok(dir) => dir,
+            Err(e) => return Ok(jail_error_response(e, who, &path_raw)),
         };
 
-        return dir_to_zip::dir_to_zip(path).await;
+        // Walking the tree and reading every file is only worth it if the body is sent
+        if req.method() == Method::HEAD {
+            return Ok(dir_to_zip::zip_head(uri_path.trim_matches('/')));
+        }
+
+        // Zips downloaded with a share link count towards its download limit
+        if let Err(e) = consume_share(share.as_ref()) {
+            return Ok(share_error_response(e));
+        }
+
+        let excluded = mounts.shadowed_paths(mount, uri_path);
+        let throttle = Throttle::of_request(&req);
+        return dir_to_zip::dir_to_zip(&mount.dir, dir, excluded, uri_path.trim_matches('/'), throttle).await;
     }
 
-    let path_metadata = match fs::metadata(path).await {
+    let (mount, path) = mounts.find(uri_path);
+    let path = match jail::resolve(&mount.dir, path).await {
+        Ok(path) => path,
+        // Directories that only exist because a mount point is inside them
+        Err(JailError::NotFound) if !mounts.virtual_dirs(uri_path).is_empty() => {
+            return Ok(directory_listing(&req, site, &path_raw, uri_path, Vec::new()));
+        }
+        Err(JailError::NotFound) => return spa_fallback(&req, site, uri_path).await,
+        Err(e) => return Ok(jail_error_response(e, who, &path_raw)),
+    };
+
+    let path_metadata = match fs::metadata(&path).await {
         Ok(metadata) => metadata,
-        Err(_) => return Ok(not_found()),
+        Err(e) if e.kind() == ErrorKind::NotFound => return spa_fallback(&req, site, uri_path).await,
+        Err(e) => return Ok(io_error_response(&e)),
     };
 
     if path_metadata.is_file() {
-        return file_send(path, path_metadata.len() as usize).await
+        return file_send(&req, site, uri_path, &path, &path_metadata, share.as_ref()).await
     }
 
-    unsafe {
-        // If the SPA file exists, serve it
-        if let Some(spa_file) = SPA_FILE.as_ref() {
-            let metadata = match spa_file.metadata() {
-                Ok(m) => m,
-                Err(e) => {
-                    print_error!("Error reading SPA file metadata: {e}");
-                    return Ok(not_found());
-                }
-            };
+    // If the SPA file exists, serve it
+    if let Some(spa_file) = &site.spa_file {
+        let metadata = match fs::metadata(spa_file).await {
+            Ok(m) => m,
+            Err(e) => {
+                print_error!("Error reading SPA file metadata: {e}");
+                return Ok(error_response());
+            }
+        };
 
-            return file_send(spa_file, metadata.len() as usize).await;
-        }
+        return file_send(&req, site, uri_path, spa_file, &metadata, None).await;
+    }
 
-        // If the --html flag is set, serve the index.html file
-        if SHOW_HTML {
-            let html_path = Path::new(path).join("index.html");
-            if let Ok(metadata) = html_path.metadata() {
-                return file_send(html_path, metadata.len() as usize).await;
+    // If the --html flag is set, serve the index.html file
+    if site.show_html {
+        if let Ok(html_path) = jail::confine(&mount.dir, &path.join("index.html")).await {
+            if let Ok(metadata) = fs::metadata(&html_path).await {
+                return file_send(&req, site, uri_path, html_path, &metadata, None).await;
             }
         }
     }
 
-    let files_in_curr_path = match get_files_in_dir2(path) {
+    let mut files_in_curr_path = match get_files_in_dir2(&path) {
         Ok(files) => files,
-        Err(_) => return Ok(not_found()),
+        Err(e) => return Ok(io_error_response(&e)),
     };
 
-    let html = build_html2(path_raw, files_in_curr_path);
-    update_stats(StatsMsg::SendedBytes(html.len() as u32));
-    Ok(index(html))
+    if !SHOW_SIDECARS.get().copied().unwrap_or_default() {
+        compress::hide_sidecars(&mut files_in_curr_path);
+    }
+
+    Ok(directory_listing(&req, site, &path_raw, uri_path, files_in_curr_path))
+}
+
+
+/// History API fallback, a missing path gets the SPA file so the client side router can handle it (`/users/42`).
+/// Paths with an extension (missing assets) and the `--spa-exclude` prefixes still get 404
+async fn spa_fallback(req: &Request<Incoming>, site: &Site, uri_path: &str) -> HyperResult<BoxBodyResponse> {
+    let spa_file = match &site.spa_file {
+        Some(spa_file) if sites::is_spa_route(uri_path, SPA_EXCLUDE.get().map(Vec::as_slice).unwrap_or_default()) => spa_file,
+        _ => return Ok(not_found()),
+    };
+
+    match fs::metadata(spa_file).await {
+        Ok(metadata) => file_send(req, site, uri_path, spa_file, &metadata, None).await,
+        Err(e) => {
+            print_error!("Error reading SPA file metadata: {e}");
+            Ok(error_response())
+        }
+    }
+}
+
+
+/// Builds the listing of a directory, adding the mount points inside it as virtual directories.
+/// `site_path` is the path inside the site, `uri_path` the requested one (they differ for share links)
+fn directory_listing(req: &Request<Incoming>, site: &Site, uri_path: &str, site_path: &str, mut files: Vec<DirectoryFile>) -> BoxBodyResponse {
+    for name in site.mounts.virtual_dirs(site_path) {
+        let file_name = format!("{name}/");
+        files.retain(|f| f.file_name != file_name && f.file_name != name);
+        files.push(DirectoryFile { is_dir: true, file_size: String::new(), file_name });
+    }
+
+    let dir_name = site_path.trim_end_matches('/').rsplit('/').next().unwrap_or_default();
+    let cache_control = cache_control(site_path, dir_name);
+
+    let html = build_html2(Cow::Borrowed(uri_path), files);
+    let encoding = COMPRESSION
+        .get()
+        .and_then(|c| c.negotiate(req.headers(), &mime_guess::mime::TEXT_HTML, html.len() as u64));
+
+    let etag = validators::content_etag(html.as_bytes());
+    let validators = Validators {
+        etag: match encoding {
+            Some(encoding) => validators::etag_with_suffix(&etag, encoding.as_str()),
+            None => etag,
+        },
+        last_modified: None,
+    };
+
+    match validators::evaluate(req.method(), req.headers(), &validators) {
+        Precondition::NotModified => return with_cache_control(not_modified(&validators), cache_control),
+        Precondition::Failed => return precondition_failed(),
+        Precondition::Proceed => {}
+    }
+
+    if req.method() != Method::HEAD {
+        update_stats(StatsMsg::SendedBytes(html.len() as u32));
+    }
+    let mut response = with_cache_control(index(html, &validators.etag), cache_control);
+    match encoding {
+        Some(encoding) => compress::encode_response(response, encoding),
+        None => {
+            if COMPRESSION.get().is_some() {
+                response.headers_mut().insert(VARY, HeaderValue::from_static("Accept-Encoding"));
+            }
+            response
+        }
+    }
+}
+
+
+/// `Cache-Control` of the `--cache-control` rule matching the file or listing
+fn cache_control(uri_path: &str, file_name: &str) -> Option<HeaderValue> {
+    CACHE_RULES.get().and_then(|rules| cache_control::cache_control(rules, uri_path, file_name))
+}
+
+
+fn with_cache_control(mut response: BoxBodyResponse, cache_control: Option<HeaderValue>) -> BoxBodyResponse {
+    if let Some(cache_control) = cache_control {
+        response.headers_mut().insert(CACHE_CONTROL, cache_control);
+    }
+    response
+}
+
+
+/// Counts a download made with a share link towards its limit
+fn consume_share(share: Option<&ShareGrant>) -> Result<(), ShareError> {
+    match (share, SHARE_KEY.get()) {
+        (Some(grant), Some(key)) => key.consume(grant),
+        _ => Ok(()),
+    }
+}
+
+
+fn share_error_response(err: ShareError) -> BoxBodyResponse {
+    match err {
+        ShareError::Invalid => forbidden(),
+        ShareError::Expired | ShareError::Exhausted => gone(),
+    }
+}
+
+
+fn jail_error_response(err: JailError, who: SocketAddr, path_raw: &str) -> BoxBodyResponse {
+    match err {
+        JailError::Forbidden => {
+            print_error!("{who} -> Blocked request outside the served root: {path_raw}");
+            forbidden()
+        }
+        JailError::NotFound => not_found(),
+        JailError::Io(e) => io_error_response(&e),
+    }
 }
 
 
@@ -222,32 +829,170 @@ fn get_files_in_dir2(path: impl AsRef<Path>) -> Result<Vec<DirectoryFile>, std::
 }
 
 
-async fn file_send(filename: impl AsRef<Path>, file_len: usize) -> HyperResult<BoxBodyResponse> {
-    //Wrap to a tokio_util::io::ReaderStream
-    let reader_stream = match File::open(&filename).await {
-        Ok(file) => ReaderInspector::new(
-            ReaderStream::with_capacity(file, CHUNK_SIZE)
-        ),
-        Err(_) => return Ok(not_found()),
+/// `uri_path` is the path inside the site, `filename` the file that answers it (the SPA file for any route).
+/// `share` is the share link the file is downloaded with
+async fn file_send(req: &Request<Incoming>, site: &Site, uri_path: &str, filename: impl AsRef<Path>, metadata: &Metadata, share: Option<&ShareGrant>) -> HyperResult<BoxBodyResponse> {
+    let filename = filename.as_ref();
+    let file_len = metadata.len();
+    let req_headers = req.headers();
+    let throttle = Throttle::of_request(req);
+
+    let file_mime = mime_guess::from_path(filename).first_or_octet_stream();
+    // HTML pages get the live reload script, the whole page is sent uncompressed
+    let live_reload = LIVE_RELOAD.get().is_some() && site.show_html && file_mime == mime_guess::mime::TEXT_HTML;
+    // Downloads (sites without --html / --spa) keep their length and can be resumed, only shown pages are compressed
+    let compressible = COMPRESSION.get().is_some() && site.show_html && compress::is_compressible(&file_mime) && !live_reload;
+
+    // A precompressed sidecar (app.js.br) is picked over compressing on the fly.
+    // Ranges are served from the uncompressed file
+    let sidecar = match req_headers.contains_key(RANGE) || live_reload {
+        true => None,
+        false => compress::find_sidecar(req_headers, filename).await,
+    };
+    let encoding = match &sidecar {
+        Some((encoding, _, _)) => Some(*encoding),
+        None if req_headers.contains_key(RANGE) || !compressible => None,
+        None => COMPRESSION.get().and_then(|c| c.negotiate(req_headers, &file_mime, file_len)),
+    };
+    let (body_file, body_metadata) = match &sidecar {
+        Some((_, sidecar_file, sidecar_metadata)) => (sidecar_file.as_path(), sidecar_metadata),
+        None => (filename, metadata),
     };
 
-    let mime = unsafe {
-        match SHOW_HTML {
-            true => mime_guess::from_path(&filename).first_or_text_plain(),
-            false => mime_guess::mime::APPLICATION_OCTET_STREAM
-        }
+    let mut validators = validators::file_validators(body_file, body_metadata).await;
+    if let Some(encoding) = encoding {
+        validators.etag = validators::etag_with_suffix(&validators.etag, encoding.as_str());
+    }
+    if live_reload {
+        validators.etag = validators::etag_with_suffix(&validators.etag, live_reload::ETAG_SUFFIX);
+    }
+
+    let file_name = filename.file_name().unwrap_or_default().to_string_lossy();
+    let cache_control = cache_control(uri_path, &file_name);
+
+    match validators::evaluate(req.method(), req_headers, &validators) {
+        Precondition::NotModified => return Ok(with_cache_control(not_modified(&validators), cache_control)),
+        Precondition::Failed => return Ok(precondition_failed()),
+        Precondition::Proceed => {}
+    }
+
+    let mime = match site.show_html {
+        true => mime_guess::from_path(filename).first_or_text_plain(),
+        false => mime_guess::mime::APPLICATION_OCTET_STREAM
     };
 
-    // Convert to http_body_util::BoxBody
-    let stream_body = StreamBody::new(reader_stream.map_ok(Frame::data)).boxed();
+    // Only honor the Range header if the If-Range validator (when sent) still matches
+    let range = match req_headers.get(RANGE).and_then(|r| r.to_str().ok()) {
+        Some(r) if !live_reload && validators::if_range_matches(req_headers, &validators) => range::parse_range(r, file_len),
+        _ => RangeRequest::Full,
+    };
+
+    // Every GET sending content counts towards the share link limit, ranges included
+    if share::is_download(req.method(), &range) {
+        if let Err(e) = consume_share(share) {
+            return Ok(share_error_response(e));
+        }
+    }
 
-    let response = Response::builder()
-        .status(StatusCode::OK)
-        .header(CONTENT_TYPE, mime.to_string())
-        .header(CONTENT_LENGTH, file_len)
+    let mut response = Response::builder()
         .header(SERVER, SERVER_NAME_HEADER)
-        .body(stream_body)
-        .unwrap();
+        .header(ETAG, &validators.etag);
 
-    Ok(response)
+    // Ranges are only served from the uncompressed file, which has another ETag than an encoded body
+    if encoding.is_none() {
+        response = response.header(ACCEPT_RANGES, "bytes");
+    }
+
+    if let Some(last_modified) = validators.last_modified {
+        response = response.header(LAST_MODIFIED, validators::http_date(last_modified));
+    }
+
+    if compressible || sidecar.is_some() {
+        response = response.header(VARY, "Accept-Encoding");
+    }
+
+    if let Some(cache_control) = cache_control {
+        response = response.header(CACHE_CONTROL, cache_control);
+    }
+
+    let response = match range {
+        RangeRequest::Full if live_reload => {
+            let html = match fs::read(filename).await {
+                Ok(html) => live_reload::inject(&html),
+                Err(e) => return Ok(io_error_response(&e)),
+            };
+
+            response
+                .status(StatusCode::OK)
+                .header(CONTENT_TYPE, mime.to_string())
+                .header(CONTENT_LENGTH, html.len())
+                .body(inspected_body(Cursor::new(html), throttle))
+        }
+        RangeRequest::Full => {
+            let file = match File::open(body_file).await {
+                Ok(file) => file,
+                Err(e) => return Ok(io_error_response(&e)),
+            };
+
+            let response = response
+                .status(StatusCode::OK)
+                .header(CONTENT_TYPE, mime.to_string());
+
+            match encoding {
+                Some(encoding) if sidecar.is_some() => response
+                    .header(CONTENT_ENCODING, encoding.as_str())
+                    .header(CONTENT_LENGTH, body_metadata.len())
+                    .body(inspected_body(file, throttle)),
+                // The compressed length isn't known upfront
+                Some(encoding) => response
+                    .header(CONTENT_ENCODING, encoding.as_str())
+                    .body(inspected_body(compress::encode(file, encoding), throttle)),
+                None => response
+                    .header(CONTENT_LENGTH, file_len)
+                    .body(inspected_body(file, throttle)),
+            }
+        }
+        RangeRequest::Unsatisfiable => return Ok(range_not_satisfiable(file_len)),
+        RangeRequest::Partial(ranges) if ranges.len() == 1 => {
+            let range = ranges[0];
+            let reader = match range::range_reader(filename, range).await {
+                Ok(reader) => reader,
+                Err(e) => return Ok(io_error_response(&e)),
+            };
+
+            response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(CONTENT_TYPE, mime.to_string())
+                .header(CONTENT_RANGE, range.content_range(file_len))
+                .header(CONTENT_LENGTH, range.len())
+                .body(inspected_body(reader, throttle))
+        }
+        RangeRequest::Partial(ranges) => {
+            let multipart = match range::multipart_ranges(filename, &ranges, mime.as_ref(), file_len).await {
+                Ok(multipart) => multipart,
+                Err(e) => return Ok(io_error_response(&e)),
+            };
+
+            response
+                .status(StatusCode::PARTIAL_CONTENT)
+                .header(CONTENT_TYPE, multipart.content_type)
+                .header(CONTENT_LENGTH, multipart.content_length)
+                .body(inspected_body(multipart.reader, throttle))
+        }
+    };
+
+    Ok(response.unwrap())
+}
+
+
+/// Wraps a reader into a body that reports the sent bytes to the stats logger
+/// and keeps them under the bandwidth limits
+fn inspected_body<R: AsyncRead + Send + Sync + 'static>(reader: R, throttle: Throttle) -> BoxBody<Bytes, std::io::Error> {
+    let reader_stream = ReaderInspector::new(
+        ReaderStream::with_capacity(reader, CHUNK_SIZE),
+        throttle,
+    );
+
+    // Convert to http_body_util::BoxBody
+    StreamBody::new(reader_stream.map_ok(Frame::data)).boxed()
 }
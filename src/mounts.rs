@@ -0,0 +1,70 @@
+use std::path::{Path, PathBuf};
+
+
+/// Directory served under a URL prefix
+pub struct Mount {
+    /// `/assets` (no trailing slash), empty for the root mount
+    pub prefix: String,
+    /// Canonical path of the directory
+    pub dir: PathBuf,
+}
+
+/// Mount table, sorted from the longest to the shortest prefix
+pub struct Mounts(Vec<Mount>);
+
+/// `true` if `path` is `prefix` itself or something inside it
+fn is_under(path: &str, prefix: &str) -> bool {
+    match path.strip_prefix(prefix) {
+        Some(rest) => rest.is_empty() || rest.starts_with('/') || prefix.is_empty(),
+        None => false,
+    }
+}
+
+impl Mounts {
+    pub fn new(root: PathBuf, mut mounts: Vec<Mount>) -> Self {
+        mounts.push(Mount { prefix: String::new(), dir: root });
+        mounts.sort_by_key(|m| std::cmp::Reverse(m.prefix.len()));
+        Self(mounts)
+    }
+
+    /// Directories of every mount, the root included
+    pub fn dirs(&self) -> impl Iterator<Item = &Path> {
+        self.0.iter().map(|m| m.dir.as_path())
+    }
+
+    /// Picks the mount with the longest prefix matching the URI path,
+    /// returns it with the rest of the path (relative to the mount dir)
+    pub fn find<'a>(&self, uri_path: &'a str) -> (&Mount, &'a str) {
+        self.0
+            .iter()
+            .find(|m| is_under(uri_path, &m.prefix))
+            .map(|m| (m, &uri_path[m.prefix.len()..]))
+            .expect("the root mount matches every path")
+    }
+
+    /// Names of the mount points directly inside `uri_dir` (`/a/b` is shown as `a` inside `/`)
+    pub fn virtual_dirs(&self, uri_dir: &str) -> Vec<String> {
+        let uri_dir = uri_dir.trim_end_matches('/');
+        let mut names = self.0
+            .iter()
+            .filter(|m| m.prefix.len() > uri_dir.len() && is_under(&m.prefix, uri_dir))
+            .filter_map(|m| m.prefix[uri_dir.len()..].split('/').find(|s| !s.is_empty()))
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Paths inside `mount` (below `uri_dir`) hidden by other mounts with a longer prefix,
+    /// so the zip of a directory doesn't cross into them
+    pub fn shadowed_paths(&self, mount: &Mount, uri_dir: &str) -> Vec<PathBuf> {
+        let uri_dir = uri_dir.trim_end_matches('/');
+        self.0
+            .iter()
+            .filter(|m| m.prefix.len() > uri_dir.len() && is_under(&m.prefix, uri_dir))
+            .map(|m| mount.dir.join(m.prefix[mount.prefix.len()..].trim_start_matches('/')))
+            .collect()
+    }
+}
@@ -0,0 +1,217 @@
+use std::{io::{Cursor, SeekFrom}, path::Path, time::{SystemTime, UNIX_EPOCH}};
+use tokio::{fs::File, io::{AsyncRead, AsyncReadExt, AsyncSeekExt}};
+
+/// Requests with more ranges than this (after merging) are answered with the full file
+const MAX_RANGES: usize = 32;
+
+pub type BoxedReader = Box<dyn AsyncRead + Send + Sync + Unpin>;
+
+
+/// Inclusive byte range, as written in the `Range` / `Content-Range` headers
+#[derive(Debug, Clone, Copy)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+impl ByteRange {
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    pub fn content_range(&self, file_len: u64) -> String {
+        format!("bytes {}-{}/{}", self.start, self.end, file_len)
+    }
+}
+
+pub enum RangeRequest {
+    /// Missing or invalid `Range` header, the whole file is sent
+    Full,
+    /// One or more satisfiable ranges, sorted and merged
+    Partial(Vec<ByteRange>),
+    /// Valid `Range` header but none of the ranges overlap the file
+    Unsatisfiable,
+}
+
+
+/// Parses a `Range` header value (`bytes=0-499, 1000-, -500`) against the length of the file
+pub fn parse_range(header: &str, file_len: u64) -> RangeRequest {
+    let header = header.trim();
+    let Some(specs) = header
+        .get(..6)
+        .filter(|unit| unit.eq_ignore_ascii_case("bytes="))
+        .map(|_| &header[6..])
+    else {
+        return RangeRequest::Full;
+    };
+
+    let mut ranges = Vec::new();
+
+    for spec in specs.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let Some((start, end)) = spec.split_once('-') else {
+            return RangeRequest::Full;
+        };
+
+        let range = match (start.trim(), end.trim()) {
+            // suffix range: last N bytes
+            ("", suffix) => {
+                let Ok(suffix) = suffix.parse::<u64>() else {
+                    return RangeRequest::Full;
+                };
+                if suffix == 0 || file_len == 0 {
+                    continue;
+                }
+                ByteRange { start: file_len.saturating_sub(suffix), end: file_len - 1 }
+            }
+            (start, end) => {
+                let Ok(start) = start.parse::<u64>() else {
+                    return RangeRequest::Full;
+                };
+                let end = match end {
+                    "" => u64::MAX,
+                    end => match end.parse::<u64>() {
+                        Ok(end) if end >= start => end,
+                        _ => return RangeRequest::Full,
+                    },
+                };
+                if start >= file_len {
+                    continue;
+                }
+                ByteRange { start, end: end.min(file_len - 1) }
+            }
+        };
+
+        ranges.push(range);
+    }
+
+    if ranges.is_empty() {
+        return RangeRequest::Unsatisfiable;
+    }
+
+    // Merge overlapping and adjacent ranges so a client can't request the same bytes many times
+    ranges.sort_by_key(|r| r.start);
+    let mut merged: Vec<ByteRange> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end.saturating_add(1) => {
+                last.end = last.end.max(range.end);
+            }
+            _ => merged.push(range),
+        }
+    }
+
+    if merged.len() > MAX_RANGES {
+        return RangeRequest::Full;
+    }
+
+    RangeRequest::Partial(merged)
+}
+
+
+/// Opens the file and returns a reader limited to the given range
+pub async fn range_reader(path: &Path, range: ByteRange) -> std::io::Result<tokio::io::Take<File>> {
+    let mut file = File::open(path).await?;
+    file.seek(SeekFrom::Start(range.start)).await?;
+    Ok(file.take(range.len()))
+}
+
+
+/// `multipart/byteranges` body for requests with more than one range
+pub struct MultipartRanges {
+    pub reader: BoxedReader,
+    pub content_type: String,
+    pub content_length: u64,
+}
+
+pub async fn multipart_ranges(
+    path: &Path,
+    ranges: &[ByteRange],
+    mime: &str,
+    file_len: u64,
+) -> std::io::Result<MultipartRanges> {
+    let boundary = format!(
+        "{:016x}{:08x}",
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or_default(),
+        file_len as u32
+    );
+
+    let mut reader: BoxedReader = Box::new(tokio::io::empty());
+    let mut content_length = 0;
+
+    for range in ranges {
+        let part_header = format!(
+            "\r\n--{boundary}\r\nContent-Type: {mime}\r\nContent-Range: {}\r\n\r\n",
+            range.content_range(file_len)
+        );
+        content_length += part_header.len() as u64 + range.len();
+
+        let part = range_reader(path, *range).await?;
+        reader = Box::new(reader.chain(Cursor::new(part_header.into_bytes())).chain(part));
+    }
+
+    let closing = format!("\r\n--{boundary}--\r\n");
+    content_length += closing.len() as u64;
+    reader = Box::new(reader.chain(Cursor::new(closing.into_bytes())));
+
+    Ok(MultipartRanges {
+        reader,
+        content_type: format!("multipart/byteranges; boundary={boundary}"),
+        content_length,
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(header: &str, file_len: u64) -> Option<Vec<(u64, u64)>> {
+        match parse_range(header, file_len) {
+            RangeRequest::Partial(ranges) => Some(ranges.iter().map(|r| (r.start, r.end)).collect()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn parses_single_ranges() {
+        assert_eq!(ranges("bytes=0-499", 1000), Some(vec![(0, 499)]));
+        assert_eq!(ranges("bytes=500-2000", 1000), Some(vec![(500, 999)]));
+        assert_eq!(ranges("BYTES= 10 - 19 ", 1000), Some(vec![(10, 19)]));
+    }
+
+    #[test]
+    fn parses_suffix_ranges() {
+        assert_eq!(ranges("bytes=-100", 1000), Some(vec![(900, 999)]));
+        assert_eq!(ranges("bytes=-5000", 1000), Some(vec![(0, 999)]));
+        assert!(matches!(parse_range("bytes=-0", 1000), RangeRequest::Unsatisfiable));
+    }
+
+    #[test]
+    fn parses_open_ended_ranges() {
+        assert_eq!(ranges("bytes=900-", 1000), Some(vec![(900, 999)]));
+        assert!(matches!(parse_range("bytes=1000-", 1000), RangeRequest::Unsatisfiable));
+    }
+
+    #[test]
+    fn merges_overlapping_and_adjacent_ranges() {
+        assert_eq!(ranges("bytes=0-99, 50-149, 150-199", 1000), Some(vec![(0, 199)]));
+        assert_eq!(ranges("bytes=500-599, 0-9, -100", 1000), Some(vec![(0, 9), (500, 599), (900, 999)]));
+        assert_eq!(ranges("bytes=0-0, 0-0, 0-0", 1000), Some(vec![(0, 0)]));
+    }
+
+    #[test]
+    fn sends_the_full_file_for_too_many_ranges() {
+        let header = format!("bytes={}", (0..=MAX_RANGES).map(|i| format!("{}-{}", i * 10, i * 10)).collect::<Vec<_>>().join(","));
+        assert!(matches!(parse_range(&header, 1000), RangeRequest::Full));
+
+        let header = format!("bytes={}", (0..MAX_RANGES).map(|i| format!("{}-{}", i * 10, i * 10)).collect::<Vec<_>>().join(","));
+        assert_eq!(ranges(&header, 1000).map(|r| r.len()), Some(MAX_RANGES));
+    }
+
+    #[test]
+    fn ignores_invalid_headers() {
+        assert!(matches!(parse_range("items=0-10", 1000), RangeRequest::Full));
+        assert!(matches!(parse_range("bytes=10-5", 1000), RangeRequest::Full));
+        assert!(matches!(parse_range("bytes=abc", 1000), RangeRequest::Full));
+        assert!(matches!(parse_range("bytes=0-10, x-", 1000), RangeRequest::Full));
+    }
+}
@@ -0,0 +1,121 @@
+use std::{collections::HashMap, net::IpAddr, sync::Mutex, time::{Duration, Instant}};
+use crate::logger::{update_stats, StatsMsg};
+
+/// Clients idle for longer than this are forgotten once the table grows
+const IDLE_CLIENT: Duration = Duration::from_secs(60);
+const PRUNE_THRESHOLD: usize = 1024;
+
+
+struct ClientState {
+    tokens: f64,
+    last_refill: Instant,
+    connections: u32,
+    /// Set while the client is over the limit, so only the first rejection is logged
+    throttled: bool,
+}
+
+/// Token bucket (requests/s + burst) and concurrent connections limit per client IP
+pub struct RateLimiter {
+    pub rate: Option<f64>,
+    pub burst: f64,
+    pub max_connections: Option<u32>,
+    clients: Mutex<HashMap<IpAddr, ClientState>>,
+}
+
+/// Keeps one connection slot of the client until dropped
+pub struct ConnectionSlot {
+    limiter: &'static RateLimiter,
+    ip: IpAddr,
+}
+
+
+impl RateLimiter {
+    pub fn new(rate: Option<f64>, burst: Option<u32>, max_connections: Option<u32>) -> Self {
+        let burst = burst
+            .map(f64::from)
+            .unwrap_or_else(|| rate.unwrap_or(1.0).ceil())
+            .max(1.0);
+
+        Self { rate, burst, max_connections, clients: Mutex::default() }
+    }
+
+    fn with_client<T>(&self, ip: IpAddr, f: impl FnOnce(&mut ClientState) -> T) -> T {
+        let mut clients = self.clients.lock().unwrap();
+        let now = Instant::now();
+
+        if clients.len() > PRUNE_THRESHOLD {
+            clients.retain(|_, c| c.connections > 0 || now - c.last_refill < IDLE_CLIENT);
+        }
+
+        let client = clients.entry(ip.to_canonical()).or_insert(ClientState {
+            tokens: self.burst,
+            last_refill: now,
+            connections: 0,
+            throttled: false,
+        });
+
+        if let Some(rate) = self.rate {
+            let elapsed = (now - client.last_refill).as_secs_f64();
+            client.tokens = (client.tokens + elapsed * rate).min(self.burst);
+        }
+        client.last_refill = now;
+
+        f(client)
+    }
+
+    fn throttle(client: &mut ClientState, ip: IpAddr, reason: &str) {
+        update_stats(StatsMsg::Throttled);
+        if !client.throttled {
+            client.throttled = true;
+            print_error!("{ip} -> Throttled, {reason}");
+        }
+    }
+
+    /// Takes a token for a request, returns the time to wait for the next one if there are none left
+    pub fn check_request(&self, ip: IpAddr) -> Result<(), Duration> {
+        let Some(rate) = self.rate else {
+            return Ok(());
+        };
+
+        self.with_client(ip, |client| {
+            if client.tokens >= 1.0 {
+                client.tokens -= 1.0;
+                client.throttled = false;
+                return Ok(());
+            }
+
+            Self::throttle(client, ip, "too many requests");
+            Err(Duration::from_secs_f64((1.0 - client.tokens) / rate))
+        })
+    }
+
+    /// Takes a connection slot, `None` if the client already has too many connections open
+    pub fn connect(&'static self, ip: IpAddr) -> Option<ConnectionSlot> {
+        let slot = || ConnectionSlot { limiter: self, ip: ip.to_canonical() };
+        let Some(max_connections) = self.max_connections else {
+            return Some(slot());
+        };
+
+        self.with_client(ip, |client| {
+            if client.connections >= max_connections {
+                Self::throttle(client, ip, "too many connections");
+                return None;
+            }
+
+            client.connections += 1;
+            Some(slot())
+        })
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        if self.limiter.max_connections.is_none() {
+            return;
+        }
+
+        if let Some(client) = self.limiter.clients.lock().unwrap().get_mut(&self.ip) {
+            client.connections = client.connections.saturating_sub(1);
+        }
+    }
+}
@@ -1,22 +1,30 @@
-use std::{pin::Pin, task::{Context, Poll}};
+use std::{future::Future, pin::Pin, task::{ready, Context, Poll}};
+
+use bytes::Bytes;
 
 use futures_util::Stream;
 use pin_project_lite::pin_project;
-use tokio::io::AsyncRead;
+use tokio::{io::AsyncRead, time::Sleep};
 use tokio_util::io::ReaderStream;
 
 use crate::logger::{update_stats, StatsMsg};
+use crate::throttle::Throttle;
 
 
 pin_project! {
     pub struct ReaderInspector<R: AsyncRead> {
         #[pin]
-        r: ReaderStream<R>
+        r: ReaderStream<R>,
+        throttle: Throttle,
+        // Pause after a chunk while the sent bytes are over the bandwidth limits
+        sleep: Option<Pin<Box<Sleep>>>,
+        // Rest of a chunk bigger than what the bandwidth limits allow at once
+        pending: Option<Bytes>,
     }
 }
 impl<R: AsyncRead> ReaderInspector<R> {
-    pub fn new(r: ReaderStream<R>) -> Self {
-        Self { r }
+    pub fn new(r: ReaderStream<R>, throttle: Throttle) -> Self {
+        Self { r, throttle, sleep: None, pending: None }
     }
 }
 
@@ -24,12 +32,35 @@ impl<R: AsyncRead> Stream for ReaderInspector<R> {
     type Item = <ReaderStream<R> as Stream>::Item;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        let r = self.project().r.poll_next(cx);
+        let this = self.project();
+
+        if let Some(sleep) = this.sleep {
+            if sleep.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            *this.sleep = None;
+        }
+
+        let mut chunk = match this.pending.take() {
+            Some(chunk) => chunk,
+            None => match ready!(this.r.poll_next(cx)) {
+                Some(Ok(chunk)) => chunk,
+                other => return Poll::Ready(other),
+            },
+        };
 
-        if let Poll::Ready(Some(Ok(chunk))) = &r {
-            update_stats(StatsMsg::SendedBytes(chunk.len() as u32));
+        let max_chunk = this.throttle.max_chunk();
+        if chunk.len() > max_chunk {
+            *this.pending = Some(chunk.split_off(max_chunk));
         }
 
-        r
+        update_stats(StatsMsg::SendedBytes(chunk.len() as u32));
+
+        let delay = this.throttle.delay(chunk.len());
+        if !delay.is_zero() {
+            *this.sleep = Some(Box::pin(tokio::time::sleep(delay)));
+        }
+
+        Poll::Ready(Some(Ok(chunk)))
     }
-} 
+}
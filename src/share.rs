@@ -0,0 +1,236 @@
+use std::{collections::HashMap, sync::Mutex, time::{SystemTime, UNIX_EPOCH}};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD as BASE64_URL, Engine};
+use hmac::{Hmac, Mac};
+use hyper::Method;
+use sha2::Sha256;
+use crate::range::RangeRequest;
+
+/// Share links look like `/.share/<expires>-<max downloads>-<signature>/shared/path`
+pub const SHARE_PREFIX: &str = "/.share/";
+
+
+pub struct ShareKey {
+    secret: Vec<u8>,
+    /// Downloads made with each link, keyed by signature
+    downloads: Mutex<HashMap<String, u32>>,
+}
+
+/// A request carrying a valid share link
+pub struct ShareGrant {
+    signature: String,
+    max_downloads: u32,
+}
+
+pub enum ShareError {
+    /// Malformed link or wrong signature
+    Invalid,
+    /// Past the expiry date
+    Expired,
+    /// All the allowed downloads were already made
+    Exhausted,
+}
+
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Requests that use up a download of the link: every GET sending file content, ranges included,
+/// otherwise `Range: bytes=0-` would get the whole file for free. HEAD and 304 / 412 / 416 responses don't
+pub fn is_download(method: &Method, range: &RangeRequest) -> bool {
+    method == Method::GET && !matches!(range, RangeRequest::Unsatisfiable)
+}
+
+/// `/docs/` and `docs` are the same scope, the root is an empty scope
+fn normalize_scope(scope: &str) -> String {
+    let scope = scope.trim_matches('/');
+    match scope.is_empty() {
+        true => String::new(),
+        false => format!("/{scope}"),
+    }
+}
+
+impl ShareKey {
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        Self { secret: secret.into(), downloads: Mutex::default() }
+    }
+
+    fn mac(&self, scope: &str, expires: u64, max_downloads: u32) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts keys of any size");
+        mac.update(format!("{scope}\n{expires}\n{max_downloads}").as_bytes());
+        mac
+    }
+
+    /// Builds the path of a link to `scope` (file or directory) valid until `expires` (unix time).
+    /// `max_downloads` of 0 means unlimited
+    pub fn link(&self, scope: &str, expires: u64, max_downloads: u32) -> String {
+        let scope = normalize_scope(scope);
+        let signature = BASE64_URL.encode(self.mac(&scope, expires, max_downloads).finalize().into_bytes());
+
+        let encoded_scope = scope
+            .split('/')
+            .map(|segment| urlencoding::encode(segment).into_owned())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        format!("{SHARE_PREFIX}{expires}-{max_downloads}-{signature}{encoded_scope}")
+    }
+
+    /// Checks a (decoded) share link path. Returns `None` if it isn't a share link,
+    /// otherwise the grant and the path inside the site
+    pub fn verify<'a>(&self, uri_path: &'a str) -> Option<Result<(ShareGrant, &'a str), ShareError>> {
+        let rest = uri_path.strip_prefix(SHARE_PREFIX)?;
+        let (token, path) = match rest.find('/') {
+            Some(i) => rest.split_at(i),
+            None => (rest, ""),
+        };
+
+        let mut parts = token.splitn(3, '-');
+        let (Some(expires), Some(max_downloads), Some(signature)) = (parts.next(), parts.next(), parts.next()) else {
+            return Some(Err(ShareError::Invalid));
+        };
+        let (Ok(expires), Ok(max_downloads), Ok(raw_signature)) = (
+            expires.parse::<u64>(),
+            max_downloads.parse::<u32>(),
+            BASE64_URL.decode(signature),
+        ) else {
+            return Some(Err(ShareError::Invalid));
+        };
+
+        // The link is valid for its scope and everything inside it, so every parent
+        // of the requested path (the path itself included) is a candidate scope
+        let trimmed = path.trim_end_matches('/');
+        let signed = std::iter::once(trimmed.len())
+            .chain(trimmed.rmatch_indices('/').map(|(i, _)| i))
+            .any(|end| {
+                self.mac(&trimmed[..end], expires, max_downloads)
+                    .verify_slice(&raw_signature)
+                    .is_ok()
+            });
+
+        if !signed {
+            return Some(Err(ShareError::Invalid));
+        }
+
+        if unix_now() > expires {
+            return Some(Err(ShareError::Expired));
+        }
+
+        let grant = ShareGrant { signature: signature.to_string(), max_downloads };
+        if grant.max_downloads > 0 && self.downloads.lock().unwrap().get(&grant.signature) >= Some(&grant.max_downloads) {
+            return Some(Err(ShareError::Exhausted));
+        }
+
+        Some(Ok((grant, path)))
+    }
+
+    /// Counts a download (file or zip) made with the link
+    pub fn consume(&self, grant: &ShareGrant) -> Result<(), ShareError> {
+        if grant.max_downloads == 0 {
+            return Ok(());
+        }
+
+        let mut downloads = self.downloads.lock().unwrap();
+        let count = downloads.entry(grant.signature.clone()).or_default();
+        if *count >= grant.max_downloads {
+            return Err(ShareError::Exhausted);
+        }
+
+        *count += 1;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn verify(key: &ShareKey, link: &str) -> Result<String, &'static str> {
+        match key.verify(link) {
+            None => Err("not a share link"),
+            Some(Ok((_, path))) => Ok(path.to_string()),
+            Some(Err(ShareError::Invalid)) => Err("invalid"),
+            Some(Err(ShareError::Expired)) => Err("expired"),
+            Some(Err(ShareError::Exhausted)) => Err("exhausted"),
+        }
+    }
+
+    #[test]
+    fn verifies_the_scope_and_everything_inside_it() {
+        let key = ShareKey::new("secret");
+        let link = key.link("/docs/", unix_now() + 60, 0);
+
+        assert_eq!(verify(&key, &link), Ok("/docs".to_string()));
+        assert_eq!(verify(&key, &format!("{link}/")), Ok("/docs/".to_string()));
+        assert_eq!(verify(&key, &format!("{link}/a/b.txt")), Ok("/docs/a/b.txt".to_string()));
+        assert_eq!(verify(&key, "/docs/a.txt"), Err("not a share link"));
+    }
+
+    #[test]
+    fn rejects_paths_outside_the_scope() {
+        let key = ShareKey::new("secret");
+        let link = key.link("/docs", unix_now() + 60, 0);
+        let token = link.strip_suffix("/docs").unwrap();
+
+        assert_eq!(verify(&key, &format!("{token}/docs-private/a.txt")), Err("invalid"));
+        assert_eq!(verify(&key, &format!("{token}/other.txt")), Err("invalid"));
+        assert_eq!(verify(&key, token), Err("invalid"));
+    }
+
+    #[test]
+    fn root_links_share_everything() {
+        let key = ShareKey::new("secret");
+        let link = key.link("/", unix_now() + 60, 0);
+        assert_eq!(verify(&key, &format!("{link}/any/file.txt")), Ok("/any/file.txt".to_string()));
+    }
+
+    #[test]
+    fn rejects_tampered_links() {
+        let key = ShareKey::new("secret");
+        let expires = unix_now() + 60;
+        let link = key.link("/file.txt", expires, 1);
+
+        assert_eq!(verify(&ShareKey::new("other"), &link), Err("invalid"));
+        assert_eq!(verify(&key, &link.replacen(&format!("{expires}-1-"), &format!("{}-1-", expires + 1), 1)), Err("invalid"));
+        assert_eq!(verify(&key, &link.replacen(&format!("{expires}-1-"), &format!("{expires}-2-"), 1)), Err("invalid"));
+        assert_eq!(verify(&key, "/.share/garbage/file.txt"), Err("invalid"));
+    }
+
+    #[test]
+    fn rejects_expired_links() {
+        let key = ShareKey::new("secret");
+        let link = key.link("/file.txt", unix_now() - 1, 0);
+        assert_eq!(verify(&key, &link), Err("expired"));
+    }
+
+    #[test]
+    fn counts_downloads() {
+        let key = ShareKey::new("secret");
+        let link = key.link("/file.txt", unix_now() + 60, 2);
+
+        for _ in 0..2 {
+            let Some(Ok((grant, _))) = key.verify(&link) else {
+                panic!("the link should be valid");
+            };
+            assert!(key.consume(&grant).is_ok());
+        }
+        assert_eq!(verify(&key, &link), Err("exhausted"));
+    }
+
+    #[test]
+    fn counts_range_downloads() {
+        let full = crate::range::parse_range("bytes=0-", 1000);
+        let resumed = crate::range::parse_range("bytes=500-", 1000);
+        assert!(is_download(&Method::GET, &RangeRequest::Full));
+        assert!(is_download(&Method::GET, &full));
+        assert!(is_download(&Method::GET, &resumed));
+
+        assert!(!is_download(&Method::HEAD, &RangeRequest::Full));
+        assert!(!is_download(&Method::HEAD, &full));
+        assert!(!is_download(&Method::GET, &crate::range::parse_range("bytes=2000-", 1000)));
+    }
+}
@@ -0,0 +1,106 @@
+use std::{collections::HashMap, path::{Path, PathBuf}};
+use hyper::{body::Incoming, header::HOST, Request};
+use crate::mounts::{Mount, Mounts};
+
+
+/// Everything needed to serve one site (the default one or a `--vhost`)
+pub struct Site {
+    pub mounts: Mounts,
+    pub show_html: bool,
+    pub spa_file: Option<PathBuf>,
+}
+
+pub struct Sites {
+    pub default: Site,
+    /// Keyed by lowercase host name (without port)
+    pub vhosts: HashMap<String, Site>,
+}
+
+
+pub fn canonical_dir(dir: &Path) -> Result<PathBuf, String> {
+    match dir.canonicalize() {
+        Ok(dir) if dir.is_dir() => Ok(dir),
+        Ok(_) => Err(format!("{} is not a directory", dir.display())),
+        Err(e) => Err(format!("Failed to resolve {}: {e}", dir.display())),
+    }
+}
+
+impl Site {
+    /// Resolves the site dirs, `name` is prepended to the log messages (`[docs.lan] `).
+    /// Fails if the root dir is missing, invalid mounts and SPA files are ignored
+    pub fn new(name: &str, root: &Path, mounts: Vec<(String, PathBuf)>, show_html: bool, spa_file: Option<PathBuf>) -> Result<Self, String> {
+        // Every served path is checked against the canonical dir of its mount
+        let root = canonical_dir(root).map_err(|e| format!("{name}[--root] {e}"))?;
+        print_info!("{name}Serving {}", root.display());
+
+        let mounts = mounts
+            .into_iter()
+            .filter_map(|(prefix, dir)| match canonical_dir(&dir) {
+                Ok(dir) => {
+                    print_info!("{name}Serving {} at {prefix}/", dir.display());
+                    Some(Mount { prefix, dir })
+                }
+                Err(e) => {
+                    print_error!("{name}[--mount] Ignoring {prefix}: {e}");
+                    None
+                }
+            })
+            .collect();
+
+        // Only keep the SPA file if it exists
+        let spa_file = spa_file.filter(|spa_file_path| {
+            if spa_file_path.exists() {
+                print_info!("{name}SPA file set to: {}", spa_file_path.display());
+                true
+            } else {
+                print_error!(0; "{name}[--spa] File {} does not exist in the served dir", spa_file_path.display());
+                false
+            }
+        });
+
+        Ok(Self {
+            mounts: Mounts::new(root, mounts),
+            show_html,
+            spa_file,
+        })
+    }
+}
+
+impl Sites {
+    /// Picks the site by the `Host` header (or the `:authority` of HTTP/2 requests),
+    /// unknown hosts get the default site
+    pub fn for_request(&self, req: &Request<Incoming>) -> &Site {
+        request_host(req)
+            .and_then(|host| self.vhosts.get(&host))
+            .unwrap_or(&self.default)
+    }
+}
+
+/// Whether a missing path is a route of the SPA, not a missing asset (`/app.js`) or an excluded prefix (`/api`)
+pub fn is_spa_route(uri_path: &str, excluded: &[String]) -> bool {
+    let last_segment = uri_path.trim_end_matches('/').rsplit('/').next().unwrap_or_default();
+    if Path::new(last_segment).extension().is_some() {
+        return false;
+    }
+
+    !excluded.iter().any(|prefix| {
+        uri_path
+            .strip_prefix(prefix.as_str())
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    })
+}
+
+/// Lowercase host name of the request, without the port
+pub fn request_host(req: &Request<Incoming>) -> Option<String> {
+    let host = req.uri()
+        .host()
+        .or_else(|| req.headers().get(HOST).and_then(|h| h.to_str().ok()))?;
+
+    let host = match host.strip_prefix('[') {
+        // [::1]:8080
+        Some(ipv6) => ipv6.split(']').next().unwrap_or_default(),
+        None => host.split(':').next().unwrap_or_default(),
+    };
+
+    Some(host.trim_end_matches('.').to_ascii_lowercase())
+}
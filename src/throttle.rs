@@ -0,0 +1,77 @@
+use std::{sync::{Arc, Mutex}, time::{Duration, Instant}};
+use hyper::{body::Incoming, Request};
+use crate::GLOBAL_LIMIT_RATE;
+
+
+/// Token bucket of bytes, the tokens can go negative so the time to wait is known
+pub struct Bandwidth {
+    /// Bytes per second
+    rate: f64,
+    /// Tokens left and last refill
+    state: Mutex<(f64, Instant)>,
+}
+
+/// Bandwidth limits applied to a response body: the connection one (`--limit-rate`)
+/// and the one shared by every transfer (`--global-limit-rate`)
+#[derive(Clone, Default)]
+pub struct Throttle {
+    connection: Option<Arc<Bandwidth>>,
+}
+
+
+impl Bandwidth {
+    pub fn new(rate: u64) -> Self {
+        Self {
+            rate: rate as f64,
+            state: Mutex::new((0.0, Instant::now())),
+        }
+    }
+
+    /// Takes `bytes` from the bucket, returns how long to wait before sending more
+    fn consume(&self, bytes: usize) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        let (tokens, last_refill) = &mut *state;
+        let now = Instant::now();
+
+        // At most a quarter of a second worth of bytes can be sent at once after an idle period
+        *tokens = (*tokens + (now - *last_refill).as_secs_f64() * self.rate).min(self.rate / 4.0);
+        *tokens -= bytes as f64;
+        *last_refill = now;
+
+        match *tokens < 0.0 {
+            true => Duration::from_secs_f64(-*tokens / self.rate),
+            false => Duration::ZERO,
+        }
+    }
+}
+
+impl Throttle {
+    /// Every request of a connection (HTTP/2 streams included) shares its bandwidth
+    pub fn for_connection(rate: Option<u64>) -> Self {
+        Self { connection: rate.map(|rate| Arc::new(Bandwidth::new(rate))) }
+    }
+
+    /// The throttle of the connection the request came from
+    pub fn of_request(req: &Request<Incoming>) -> Self {
+        req.extensions().get::<Self>().cloned().unwrap_or_default()
+    }
+
+    /// Largest chunk to send at once, a quarter of a second of the lowest rate. The pause after
+    /// a bigger chunk could outlast the --send-timeout and pass for a client that stopped reading
+    pub fn max_chunk(&self) -> usize {
+        self.connection
+            .iter()
+            .map(|b| b.rate)
+            .chain(GLOBAL_LIMIT_RATE.get().map(|b| b.rate))
+            .map(|rate| (rate / 4.0).max(1.0) as usize)
+            .min()
+            .unwrap_or(usize::MAX)
+    }
+
+    /// Time to wait after sending `bytes`
+    pub fn delay(&self, bytes: usize) -> Duration {
+        let connection = self.connection.as_ref().map(|b| b.consume(bytes)).unwrap_or_default();
+        let global = GLOBAL_LIMIT_RATE.get().map(|b| b.consume(bytes)).unwrap_or_default();
+        connection.max(global)
+    }
+}
@@ -0,0 +1,75 @@
+use std::{net::{IpAddr, UdpSocket}, path::Path, sync::Arc};
+use anyhow::Context;
+use rustls::{pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer}, ServerConfig};
+use sha2::{Digest, Sha256};
+use tokio_rustls::TlsAcceptor;
+use crate::cli::TlsSource;
+
+
+/// Builds the TLS acceptor shared by all the HTTPS listeners
+pub fn acceptor(source: &TlsSource) -> anyhow::Result<TlsAcceptor> {
+    let (certs, key) = match source {
+        TlsSource::Files { cert, key } => load_pem_files(cert, key)?,
+        TlsSource::SelfSigned => self_signed()?,
+    };
+
+    let mut config = ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .context("Invalid certificate / private key pair")?;
+
+    // Let the client pick HTTP/2 during the handshake
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+
+fn load_pem_files(cert: &Path, key: &Path) -> anyhow::Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let certs = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Failed to read the certificate {}", cert.display()))?;
+
+    if certs.is_empty() {
+        anyhow::bail!("No certificates found in {}", cert.display());
+    }
+
+    let key = PrivateKeyDer::from_pem_file(key)
+        .with_context(|| format!("Failed to read the private key {}", key.display()))?;
+
+    Ok((certs, key))
+}
+
+
+/// Generates a certificate for localhost and the LAN IP, printing its SHA-256 fingerprint
+/// so it can be checked when the browser warns about it
+fn self_signed() -> anyhow::Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let mut names = vec!["localhost".to_string(), "127.0.0.1".to_string(), "::1".to_string()];
+    if let Some(ip) = lan_ip() {
+        names.push(ip.to_string());
+    }
+
+    let rcgen::CertifiedKey { cert, key_pair } = rcgen::generate_simple_self_signed(names.clone())
+        .context("Failed to generate the self-signed certificate")?;
+
+    let fingerprint = Sha256::digest(cert.der())
+        .iter()
+        .map(|b| format!("{b:02X}"))
+        .collect::<Vec<_>>()
+        .join(":");
+
+    print_info!("Self-signed certificate generated for {}", names.join(", "));
+    print_info!("SHA-256 fingerprint: {fingerprint}");
+
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key_pair.serialize_der()));
+    Ok((vec![cert.der().clone()], key))
+}
+
+
+/// IP address of the interface used to reach the network (no packets are sent)
+pub fn lan_ip() -> Option<IpAddr> {
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("8.8.8.8:80").ok()?;
+    socket.local_addr().ok().map(|addr| addr.ip())
+}
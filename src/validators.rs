@@ -0,0 +1,295 @@
+use std::{collections::HashMap, fs::Metadata, path::{Path, PathBuf}, sync::{Mutex, OnceLock}, time::{Duration, SystemTime, UNIX_EPOCH}};
+use chrono::{DateTime, Utc};
+use hyper::{header::{IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_RANGE, IF_UNMODIFIED_SINCE}, HeaderMap, Method};
+use sha2::{Digest, Sha256};
+use tokio::{fs::File, io::AsyncReadExt};
+use crate::{CHUNK_SIZE, ETAG_CONTENT_HASH};
+
+/// (mtime, size, etag) of an already hashed file
+type CachedHash = (SystemTime, u64, String);
+
+/// Content hashes already computed, keyed by path and invalidated when the mtime or size changes
+static HASH_CACHE: OnceLock<Mutex<HashMap<PathBuf, CachedHash>>> = OnceLock::new();
+
+
+/// Cache validators of a response
+pub struct Validators {
+    pub etag: String,
+    pub last_modified: Option<SystemTime>,
+}
+
+pub enum Precondition {
+    /// The request must be served normally
+    Proceed,
+    /// `304 Not Modified`
+    NotModified,
+    /// `412 Precondition Failed`
+    Failed,
+}
+
+
+/// Formats a `SystemTime` as an IMF-fixdate (`Sun, 06 Nov 1994 08:49:37 GMT`)
+pub fn http_date(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time)
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
+
+/// Parses an HTTP-date, only the IMF-fixdate format (the one every client sends) is supported
+pub fn parse_http_date(date: &str) -> Option<SystemTime> {
+    let parsed = DateTime::parse_from_rfc2822(date.trim()).ok()?;
+    let secs = u64::try_from(parsed.timestamp()).ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// HTTP dates have a 1 second resolution, so the file mtime is truncated before comparing
+pub fn truncate_to_secs(time: SystemTime) -> SystemTime {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+
+    UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Strong ETag from a hash of the given bytes
+pub fn content_etag(content: &[u8]) -> String {
+    let hash = Sha256::digest(content);
+    format!("\"{}\"", hex(&hash[..16]))
+}
+
+/// ETag of another representation of the same content (compressed, with the live reload script...),
+/// `"abc"` becomes `"abc-gzip"`
+pub fn etag_with_suffix(etag: &str, suffix: &str) -> String {
+    match etag.strip_suffix('"') {
+        Some(etag) => format!("{etag}-{suffix}\""),
+        None => format!("{etag}-{suffix}"),
+    }
+}
+
+/// Builds the validators of a file, the ETag is either derived from the mtime and size
+/// or (with `--etag-hash`) from a hash of the file contents
+pub async fn file_validators(path: &Path, metadata: &Metadata) -> Validators {
+    let last_modified = metadata.modified().ok();
+    let mtime = last_modified.unwrap_or(UNIX_EPOCH);
+    let len = metadata.len();
+
+    let etag = match ETAG_CONTENT_HASH.get().copied().unwrap_or_default() {
+        true => match hashed_etag(path, mtime, len).await {
+            Ok(etag) => etag,
+            Err(_) => metadata_etag(mtime, len),
+        },
+        false => metadata_etag(mtime, len),
+    };
+
+    Validators { etag, last_modified }
+}
+
+fn metadata_etag(mtime: SystemTime, len: u64) -> String {
+    let nanos = mtime
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+
+    format!("\"{nanos:x}-{len:x}\"")
+}
+
+async fn hashed_etag(path: &Path, mtime: SystemTime, len: u64) -> std::io::Result<String> {
+    let cache = HASH_CACHE.get_or_init(Default::default);
+    let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+
+    if let Some((cached_mtime, cached_len, etag)) = cache.lock().unwrap().get(&path) {
+        if *cached_mtime == mtime && *cached_len == len {
+            return Ok(etag.clone());
+        }
+    }
+
+    let mut file = File::open(&path).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; CHUNK_SIZE];
+    loop {
+        let read = file.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+
+    let etag = format!("\"{}\"", hex(&hasher.finalize()[..16]));
+    cache.lock().unwrap().insert(path, (mtime, len, etag.clone()));
+    Ok(etag)
+}
+
+
+/// Compares an `If-Match` / `If-None-Match` header against the ETag.
+/// Weak comparison ignores the `W/` prefix, strong comparison never matches weak tags
+fn etag_list_matches(header: &str, etag: &str, weak: bool) -> bool {
+    header.split(',').map(str::trim).any(|tag| {
+        if tag == "*" {
+            return true;
+        }
+
+        match weak {
+            true => tag.trim_start_matches("W/") == etag.trim_start_matches("W/"),
+            false => !tag.starts_with("W/") && !etag.starts_with("W/") && tag == etag,
+        }
+    })
+}
+
+fn header_str(headers: &HeaderMap, name: impl hyper::header::AsHeaderName) -> Option<&str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+fn modified_since(last_modified: Option<SystemTime>, date: &str) -> Option<bool> {
+    let date = parse_http_date(date)?;
+    let modified = truncate_to_secs(last_modified?);
+    Some(modified > date)
+}
+
+/// Evaluates the conditional request headers in the order defined by RFC 9110 (section 13.2.2)
+pub fn evaluate(method: &Method, headers: &HeaderMap, validators: &Validators) -> Precondition {
+    if let Some(if_match) = header_str(headers, IF_MATCH) {
+        if !etag_list_matches(if_match, &validators.etag, false) {
+            return Precondition::Failed;
+        }
+    } else if let Some(date) = header_str(headers, IF_UNMODIFIED_SINCE) {
+        if modified_since(validators.last_modified, date) == Some(true) {
+            return Precondition::Failed;
+        }
+    }
+
+    let is_get = method == Method::GET || method == Method::HEAD;
+
+    if let Some(if_none_match) = header_str(headers, IF_NONE_MATCH) {
+        if etag_list_matches(if_none_match, &validators.etag, true) {
+            return match is_get {
+                true => Precondition::NotModified,
+                false => Precondition::Failed,
+            };
+        }
+    } else if let Some(date) = header_str(headers, IF_MODIFIED_SINCE) {
+        if is_get && modified_since(validators.last_modified, date) == Some(false) {
+            return Precondition::NotModified;
+        }
+    }
+
+    Precondition::Proceed
+}
+
+/// Checks the `If-Range` header, returns `true` when the `Range` header should be honored
+pub fn if_range_matches(headers: &HeaderMap, validators: &Validators) -> bool {
+    let Some(if_range) = header_str(headers, IF_RANGE) else {
+        return true;
+    };
+
+    if if_range.starts_with('"') || if_range.starts_with("W/") {
+        return etag_list_matches(if_range, &validators.etag, false);
+    }
+
+    match (parse_http_date(if_range), validators.last_modified) {
+        (Some(date), Some(modified)) => date == truncate_to_secs(modified),
+        _ => false,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::{HeaderName, HeaderValue};
+
+    const ETAG: &str = "\"abc\"";
+
+    fn validators() -> Validators {
+        Validators { etag: ETAG.to_string(), last_modified: parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT") }
+    }
+
+    fn headers(pairs: &[(HeaderName, &str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.clone(), HeaderValue::from_str(value).unwrap()))
+            .collect()
+    }
+
+    fn evaluate_get(pairs: &[(HeaderName, &str)]) -> Precondition {
+        evaluate(&Method::GET, &headers(pairs), &validators())
+    }
+
+    #[test]
+    fn adds_etag_suffixes() {
+        assert_eq!(etag_with_suffix(ETAG, "gzip"), "\"abc-gzip\"");
+        assert_eq!(etag_with_suffix("W/\"abc\"", "lr"), "W/\"abc-lr\"");
+        assert_eq!(etag_with_suffix("abc", "br"), "abc-br");
+    }
+
+    #[test]
+    fn compares_etags() {
+        assert!(etag_list_matches("\"x\", \"abc\"", ETAG, false));
+        assert!(etag_list_matches("*", ETAG, false));
+        assert!(!etag_list_matches("\"abcd\"", ETAG, false));
+
+        // Weak tags only match with the weak comparison
+        assert!(etag_list_matches("W/\"abc\"", ETAG, true));
+        assert!(!etag_list_matches("W/\"abc\"", ETAG, false));
+        assert!(!etag_list_matches(ETAG, "W/\"abc\"", false));
+    }
+
+    #[test]
+    fn answers_not_modified() {
+        assert!(matches!(evaluate_get(&[]), Precondition::Proceed));
+        assert!(matches!(evaluate_get(&[(IF_NONE_MATCH, ETAG)]), Precondition::NotModified));
+        assert!(matches!(evaluate_get(&[(IF_NONE_MATCH, "W/\"abc\"")]), Precondition::NotModified));
+        assert!(matches!(evaluate_get(&[(IF_NONE_MATCH, "\"other\"")]), Precondition::Proceed));
+        assert!(matches!(evaluate_get(&[(IF_MODIFIED_SINCE, "Sun, 06 Nov 1994 08:49:37 GMT")]), Precondition::NotModified));
+        assert!(matches!(evaluate_get(&[(IF_MODIFIED_SINCE, "Sat, 05 Nov 1994 08:49:37 GMT")]), Precondition::Proceed));
+    }
+
+    #[test]
+    fn if_none_match_takes_precedence_over_if_modified_since() {
+        let pairs = [(IF_NONE_MATCH, "\"other\""), (IF_MODIFIED_SINCE, "Sun, 06 Nov 1994 08:49:37 GMT")];
+        assert!(matches!(evaluate_get(&pairs), Precondition::Proceed));
+    }
+
+    #[test]
+    fn if_match_takes_precedence_over_if_unmodified_since() {
+        let pairs = [(IF_MATCH, ETAG), (IF_UNMODIFIED_SINCE, "Sat, 05 Nov 1994 08:49:37 GMT")];
+        assert!(matches!(evaluate_get(&pairs), Precondition::Proceed));
+    }
+
+    #[test]
+    fn fails_preconditions() {
+        assert!(matches!(evaluate_get(&[(IF_MATCH, "\"other\"")]), Precondition::Failed));
+        assert!(matches!(evaluate_get(&[(IF_MATCH, "W/\"abc\"")]), Precondition::Failed));
+        assert!(matches!(evaluate_get(&[(IF_UNMODIFIED_SINCE, "Sat, 05 Nov 1994 08:49:37 GMT")]), Precondition::Failed));
+        assert!(matches!(evaluate_get(&[(IF_UNMODIFIED_SINCE, "Sun, 06 Nov 1994 08:49:37 GMT")]), Precondition::Proceed));
+
+        // Failed If-Match wins over a matching If-None-Match
+        assert!(matches!(evaluate_get(&[(IF_MATCH, "\"other\""), (IF_NONE_MATCH, ETAG)]), Precondition::Failed));
+    }
+
+    #[test]
+    fn matching_if_none_match_fails_other_methods() {
+        let if_none_match = headers(&[(IF_NONE_MATCH, ETAG)]);
+        assert!(matches!(evaluate(&Method::HEAD, &if_none_match, &validators()), Precondition::NotModified));
+        assert!(matches!(evaluate(&Method::POST, &if_none_match, &validators()), Precondition::Failed));
+
+        let if_modified_since = headers(&[(IF_MODIFIED_SINCE, "Sun, 06 Nov 1994 08:49:37 GMT")]);
+        assert!(matches!(evaluate(&Method::POST, &if_modified_since, &validators()), Precondition::Proceed));
+    }
+
+    #[test]
+    fn checks_if_range() {
+        let validators = validators();
+        assert!(if_range_matches(&headers(&[]), &validators));
+        assert!(if_range_matches(&headers(&[(IF_RANGE, ETAG)]), &validators));
+        assert!(!if_range_matches(&headers(&[(IF_RANGE, "\"other\"")]), &validators));
+        assert!(!if_range_matches(&headers(&[(IF_RANGE, "W/\"abc\"")]), &validators));
+        assert!(if_range_matches(&headers(&[(IF_RANGE, "Sun, 06 Nov 1994 08:49:37 GMT")]), &validators));
+        assert!(!if_range_matches(&headers(&[(IF_RANGE, "Sat, 05 Nov 1994 08:49:37 GMT")]), &validators));
+    }
+}